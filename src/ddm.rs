@@ -1,12 +1,31 @@
+use crate::{
+    error::{Error, Index, Result},
+    texture::{Color, Texture},
+    vec::Vec2,
+};
+use byteorder::{ReadBytesExt, LE};
+use dxt::Compression;
+use ndarray::Array2;
 use std::{
     fs::File,
-    io::{self, Read, Write},
+    io::{self, Cursor, Read, Seek, SeekFrom, Write},
     path::Path,
 };
 
-pub struct DDM(pub Vec<DDS>);
+/// DXT1, DXT3 and DXT5 compression
+pub mod dxt;
+
+/// a ddm file is DDS files one after the other
+#[allow(clippy::upper_case_acronyms)]
+pub struct DDM {
+    /// whatever comes before the first DDS file
+    pub prefix: Vec<u8>,
+    /// the DDS files, in order
+    pub entries: Vec<DDS>,
+}
 
 impl DDM {
+    /// split a ddm file into its DDS files
     pub fn read(path: &Path) -> io::Result<Self> {
         let mut reader = File::open(path)?;
         let mut data = Vec::new();
@@ -15,11 +34,13 @@ impl DDM {
         // - split the data into DDS files
         let separator: &[u8] = &[0x44, 0x44, 0x53, 0x20, 0x7C];
 
+        let mut prefix = data.clone();
         let mut slices = Vec::new();
         if let Some(start) = data
             .windows(separator.len())
             .position(|window| window == separator)
         {
+            prefix.truncate(start);
             let mut start = start + separator.len();
             while let Some(end) = data[start..]
                 .windows(separator.len())
@@ -31,25 +52,432 @@ impl DDM {
             slices.push(DDS(data[start - separator.len()..].to_vec()));
         }
 
-        Ok(Self(slices))
+        Ok(Self {
+            prefix,
+            entries: slices,
+        })
+    }
+
+    /// replace an entry with an edited png or dds file, keeping its format
+    pub fn replace(&mut self, index: usize, path: &Path) -> Result<()> {
+        let dds = self.entries.get(index).ok_or(Error::NotFound {
+            index: Index::Texture(index),
+        })?;
+        let replacement = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("dds") => dds.replace(&DDS(std::fs::read(path)?)),
+            // DDS images are already the right way up
+            _ => dds.encode(&Texture::read_from_image(path, false)?),
+        };
+        let replacement = replacement.map_err(|e| e.with_index(Index::Texture(index)))?;
+        self.entries[index] = replacement;
+        Ok(())
+    }
+
+    /// write a copy of the original file with the replaced DDS files, in the same order,
+    /// into out_dir
+    pub fn write_to_ddm(&self, original: &Path, out_dir: &Path) -> io::Result<()> {
+        // create dir if it does not exist
+        if !out_dir.exists() {
+            std::fs::create_dir_all(out_dir)?;
+        }
+
+        let mut file = File::create(out_dir.join(original.file_name().unwrap()))?;
+        file.write_all(&self.prefix)?;
+        for dds in self.entries.iter() {
+            file.write_all(&dds.0)?;
+        }
+        Ok(())
     }
 }
 
+/// contents of a DDS file, including the header
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone)]
 pub struct DDS(pub Vec<u8>);
 
 impl DDS {
-    pub fn write(&self, path: &Path, index: usize) -> io::Result<()> {
+    /// write to `<out_dir>/<stem>/<stem>-<index>.dds`
+    pub fn write(&self, path: &Path, out_dir: &Path, index: usize) -> io::Result<()> {
         // create dir if it does not exist
         let stem = path.file_stem().unwrap().to_str().unwrap();
-        let dir_path = format!("out/{}", stem);
-        let dir_path = Path::new(&dir_path);
+        let dir_path = out_dir.join(stem);
         if !dir_path.exists() {
-            std::fs::create_dir_all(dir_path)?;
+            std::fs::create_dir_all(&dir_path)?;
         }
 
-        let filename = format!("out/{}/{}-{}.dds", stem, stem, index);
+        let filename = dir_path.join(format!("{}-{}.dds", stem, index));
         let mut file = File::create(filename)?;
         file.write_all(&self.0)?;
         Ok(())
     }
+
+    /// decode and write to `<out_dir>/<stem>/<stem>-<index>.png`
+    pub fn write_to_image(&self, path: &Path, out_dir: &Path, index: usize) -> Result<()> {
+        let texture = self.decode()?;
+
+        // create dir if it does not exist
+        let stem = path.file_stem().unwrap().to_str().unwrap();
+        let dir_path = out_dir.join(stem);
+        if !dir_path.exists() {
+            std::fs::create_dir_all(&dir_path)?;
+        }
+
+        // DDS images are already the right way up
+        texture.write_to_image(&dir_path.join(format!("{}-{}.png", stem, index)), false)
+    }
+
+    /// read the DDS header at the start of the DDS file
+    pub fn header(&self) -> Result<DDSHeader> {
+        DDSHeader::read(&mut Cursor::new(&self.0))
+    }
+
+    /// use the replacement as is if it has the same format, otherwise re-encode it
+    pub fn replace(&self, replacement: &DDS) -> Result<DDS> {
+        let (header, new_header) = (self.header()?, replacement.header()?);
+        if header.width == new_header.width
+            && header.height == new_header.height
+            && header.mip_map_count == new_header.mip_map_count
+            && header.pixel_format == new_header.pixel_format
+        {
+            Ok(replacement.clone())
+        } else {
+            self.encode(&replacement.decode()?)
+        }
+    }
+
+    /// encode the texture with the same size, pixel format and number of mip maps
+    pub fn encode(&self, texture: &Texture) -> Result<DDS> {
+        let header = self.header()?;
+        let (width, height) = (texture.0.ncols(), texture.0.nrows());
+        if width != header.width as usize || height != header.height as usize {
+            return Err(Error::SizeMismatch {
+                offset: 0,
+                index: None,
+                expected: Vec2 {
+                    x: header.width,
+                    y: header.height,
+                },
+                found: Vec2 {
+                    x: width as _,
+                    y: height as _,
+                },
+            });
+        }
+
+        // keep the original header
+        let mut writer = Cursor::new(self.0[..DDSHeader::SIZE as usize].to_vec());
+        writer.seek(SeekFrom::End(0))?;
+
+        let mut mip_map = texture.0.clone();
+        for level in 0..header.mip_map_count {
+            if level != 0 {
+                mip_map = half(&mip_map);
+            }
+            match header.pixel_format {
+                PixelFormat::Compressed(compression) => {
+                    dxt::encode(&mut writer, &mip_map, compression)?
+                }
+                PixelFormat::Uncompressed {
+                    bit_count,
+                    masks,
+                    alpha,
+                } => {
+                    let bytes_per_pixel = bit_count as usize / 8;
+                    // only the largest mip map has padded rows
+                    let pitch = if level == 0 {
+                        header.pitch(bytes_per_pixel)
+                    } else {
+                        mip_map.ncols() * bytes_per_pixel
+                    };
+                    for row in mip_map.rows() {
+                        let mut bytes = Vec::with_capacity(pitch);
+                        for color in row.iter() {
+                            let mut value = mask(color.r, masks[0])
+                                | mask(color.g, masks[1])
+                                | mask(color.b, masks[2]);
+                            if alpha {
+                                value |= mask(color.a, masks[3]);
+                            }
+                            bytes.extend_from_slice(&value.to_le_bytes()[..bytes_per_pixel]);
+                        }
+                        bytes.resize(pitch, 0);
+                        writer.write_all(&bytes)?;
+                    }
+                }
+            }
+        }
+
+        // keep anything after the mip maps
+        let mut data = writer.into_inner();
+        if data.len() < self.0.len() {
+            data.extend_from_slice(&self.0[data.len()..]);
+        }
+        Ok(DDS(data))
+    }
+
+    /// decode the largest mip map
+    pub fn decode(&self) -> Result<Texture> {
+        let header = self.header()?;
+        let mut reader = Cursor::new(&self.0);
+        reader.seek(SeekFrom::Start(DDSHeader::SIZE))?;
+
+        let (width, height) = (header.width as usize, header.height as usize);
+        let texture = match header.pixel_format {
+            PixelFormat::Compressed(compression) => {
+                dxt::decode(&mut reader, width, height, compression)?
+            }
+            PixelFormat::Uncompressed {
+                bit_count,
+                masks,
+                alpha,
+            } => {
+                let bytes_per_pixel = bit_count as usize / 8;
+                let pitch = header.pitch(bytes_per_pixel);
+                let mut texture = Array2::default((height, width));
+                let mut row = vec![0; pitch];
+                for y in 0..height {
+                    reader.read_exact(&mut row)?;
+                    for x in 0..width {
+                        let mut bytes = [0; 4];
+                        bytes[..bytes_per_pixel]
+                            .copy_from_slice(&row[x * bytes_per_pixel..(x + 1) * bytes_per_pixel]);
+                        let value = u32::from_le_bytes(bytes);
+                        texture[[y, x]] = Color {
+                            r: unmask(value, masks[0]),
+                            g: unmask(value, masks[1]),
+                            b: unmask(value, masks[2]),
+                            a: if alpha { unmask(value, masks[3]) } else { 0xFF },
+                        };
+                    }
+                }
+                texture
+            }
+        };
+        Ok(Texture(texture, None))
+    }
+}
+
+// scale the bits under the mask to 8 bits
+fn unmask(value: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+    let max = mask >> mask.trailing_zeros();
+    let bits = (value & mask) >> mask.trailing_zeros();
+    (bits as f32 * 255.0 / max as f32).round() as _
+}
+
+// scale 8 bits to the bits under the mask
+fn mask(value: u8, mask: u32) -> u32 {
+    if mask == 0 {
+        return 0;
+    }
+    let max = mask >> mask.trailing_zeros();
+    ((value as f32 * max as f32 / 255.0).round() as u32) << mask.trailing_zeros()
+}
+
+// the next mip map, each pixel is the average of 2x2 pixels
+fn half(texture: &Array2<Color>) -> Array2<Color> {
+    let (height, width) = texture.dim();
+    let (half_height, half_width) = ((height / 2).max(1), (width / 2).max(1));
+    Array2::from_shape_fn((half_height, half_width), |(y, x)| {
+        let pixels = [(0, 0), (0, 1), (1, 0), (1, 1)]
+            .map(|(dy, dx)| texture[[(y * 2 + dy).min(height - 1), (x * 2 + dx).min(width - 1)]]);
+        let average = |channel: fn(&Color) -> u8| {
+            ((pixels.iter().map(|c| channel(c) as u32).sum::<u32>() + 2) / 4) as u8
+        };
+        Color {
+            r: average(|c| c.r),
+            g: average(|c| c.g),
+            b: average(|c| c.b),
+            a: average(|c| c.a),
+        }
+    })
+}
+
+/// pixel formats that can be decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// DXT blocks
+    Compressed(Compression),
+    /// pixels, one after the other
+    Uncompressed {
+        /// bits per pixel
+        bit_count: u32,
+        /// r, g, b, a
+        masks: [u32; 4],
+        /// whether the alpha mask is used
+        alpha: bool,
+    },
+}
+
+/// the parts of the DDS header that are needed to decode it
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy)]
+pub struct DDSHeader {
+    /// which fields of the header are valid
+    pub flags: u32,
+    /// pixels
+    pub width: u32,
+    /// pixels
+    pub height: u32,
+    /// bytes per row if the pitch flag is set, bytes of the largest mip map
+    /// if the linear size flag is set
+    pub pitch_or_linear_size: u32,
+    /// mip maps, including the largest one, at least 1
+    pub mip_map_count: u32,
+    /// how the pixels are stored
+    pub pixel_format: PixelFormat,
+}
+
+impl DDSHeader {
+    /// bytes, including the magic
+    pub const SIZE: u64 = 0x80;
+    /// pitch_or_linear_size is the pitch
+    pub const PITCH: u32 = 0x8;
+    /// pitch_or_linear_size is the linear size
+    pub const LINEAR_SIZE: u32 = 0x80000;
+
+    /// bytes per row of the largest mip map, rows might be padded.
+    /// without the pitch flag, the rows are not padded
+    pub fn pitch(&self, bytes_per_pixel: usize) -> usize {
+        let unpadded = self.width as usize * bytes_per_pixel;
+        if self.flags & Self::PITCH != 0 {
+            (self.pitch_or_linear_size as usize).max(unpadded)
+        } else {
+            unpadded
+        }
+    }
+
+    /// read the header at the start of a DDS file
+    pub fn read<T: Read + Seek>(reader: &mut T) -> Result<Self> {
+        // read magic and header size
+        const MAGIC: u32 = u32::from_le_bytes(*b"DDS ");
+        let magic = reader.read_u32::<LE>()?;
+        if magic != MAGIC {
+            return Err(Error::BadHeader {
+                offset: 0,
+                index: None,
+                what: "DDS",
+                expected: MAGIC,
+                found: magic,
+            });
+        }
+        let size = reader.read_u32::<LE>()?;
+        if size != 0x7C {
+            return Err(Error::BadHeader {
+                offset: 4,
+                index: None,
+                what: "DDS size",
+                expected: 0x7C,
+                found: size,
+            });
+        }
+
+        let flags = reader.read_u32::<LE>()?;
+        let height = reader.read_u32::<LE>()?;
+        let width = reader.read_u32::<LE>()?;
+        let pitch_or_linear_size = reader.read_u32::<LE>()?;
+
+        // skip depth
+        reader.seek(SeekFrom::Current(4))?;
+
+        // 0 also means 1
+        let mip_map_count = reader.read_u32::<LE>()?.max(1);
+
+        // skip reserved
+        reader.seek(SeekFrom::Current(4 * 11))?;
+
+        // read pixel format
+        reader.seek(SeekFrom::Current(4))?;
+        let flags_offset = reader.stream_position()?;
+        let pixel_flags = reader.read_u32::<LE>()?;
+        let mut four_cc = [0; 4];
+        reader.read_exact(&mut four_cc)?;
+        let bit_count = reader.read_u32::<LE>()?;
+        let mut masks = [0; 4];
+        for mask in masks.iter_mut() {
+            *mask = reader.read_u32::<LE>()?;
+        }
+
+        const ALPHA_PIXELS: u32 = 0x1;
+        const FOUR_CC: u32 = 0x4;
+        const RGB: u32 = 0x40;
+        let pixel_format = if pixel_flags & FOUR_CC != 0 {
+            PixelFormat::Compressed(match &four_cc {
+                b"DXT1" => Compression::Dxt1,
+                b"DXT3" => Compression::Dxt3,
+                b"DXT5" => Compression::Dxt5,
+                _ => {
+                    return Err(Error::UnsupportedEncoding {
+                        offset: flags_offset + 4,
+                        index: None,
+                        encoding: format!("DDS {}", String::from_utf8_lossy(&four_cc)),
+                    })
+                }
+            })
+        } else if pixel_flags & RGB != 0 && matches!(bit_count, 16 | 24 | 32) {
+            PixelFormat::Uncompressed {
+                bit_count,
+                masks,
+                alpha: pixel_flags & ALPHA_PIXELS != 0,
+            }
+        } else {
+            return Err(Error::UnsupportedEncoding {
+                offset: flags_offset,
+                index: None,
+                encoding: format!("DDS pixel format, flags: {:#x}", pixel_flags),
+            });
+        };
+
+        Ok(Self {
+            flags,
+            width,
+            height,
+            pitch_or_linear_size,
+            mip_map_count,
+            pixel_format,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a 2x2 DDS file with 32-bit pixels and a pitch_or_linear_size of 16
+    fn dds(flags: u32) -> DDS {
+        let mut data = b"DDS ".to_vec();
+        for value in [0x7C, flags, 2, 2, 16, 0, 1] {
+            data.extend_from_slice(&u32::to_le_bytes(value));
+        }
+        data.resize(0x4C, 0);
+        let masks = [0xFF0000, 0xFF00, 0xFF, 0xFF000000];
+        for value in [0x20, 0x41, 0, 32].into_iter().chain(masks) {
+            data.extend_from_slice(&u32::to_le_bytes(value));
+        }
+        data.resize(DDSHeader::SIZE as usize, 0);
+        // 2 rows of 16 bytes, of which the pixels are the first 8
+        for y in 0..2u8 {
+            for x in 0..16u8 {
+                data.push(y * 16 + x);
+            }
+        }
+        DDS(data)
+    }
+
+    #[test]
+    fn pitch_is_only_used_with_its_flag() {
+        let dds_with_pitch = dds(0x1007 | DDSHeader::PITCH);
+        let header = dds_with_pitch.header().unwrap();
+        assert_eq!(header.pitch(4), 16);
+        let texture = dds_with_pitch.decode().unwrap();
+        assert_eq!(texture.0[[1, 0]].b, 16);
+
+        let dds_with_linear_size = dds(0x1007 | DDSHeader::LINEAR_SIZE);
+        let header = dds_with_linear_size.header().unwrap();
+        assert_eq!(header.pitch(4), 8);
+        let texture = dds_with_linear_size.decode().unwrap();
+        assert_eq!(texture.0[[1, 0]].b, 8);
+    }
 }
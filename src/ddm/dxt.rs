@@ -0,0 +1,415 @@
+use crate::texture::Color;
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+use ndarray::Array2;
+use std::io::{self, Read, Write};
+
+// DXT textures are made of 4x4 pixel blocks, stored row by row
+
+/// the DXT compressions that can be decoded and encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// colors only, or 1 bit of alpha
+    Dxt1,
+    /// colors and 4 bits of alpha per pixel
+    Dxt3,
+    /// colors and interpolated alpha
+    Dxt5,
+}
+
+/// decode width x height pixels from the blocks in reader
+pub fn decode<T: Read>(
+    reader: &mut T,
+    width: usize,
+    height: usize,
+    compression: Compression,
+) -> io::Result<Array2<Color>> {
+    let mut texture = Array2::default((height, width));
+    for block_y in (0..height).step_by(4) {
+        for block_x in (0..width).step_by(4) {
+            let alpha = match compression {
+                Compression::Dxt1 => None,
+                Compression::Dxt3 => Some(read_explicit_alpha(reader)?),
+                Compression::Dxt5 => Some(read_interpolated_alpha(reader)?),
+            };
+            let colors = read_colors(reader, compression == Compression::Dxt1)?;
+
+            // blocks can hang off the edge of small textures
+            for y in 0..4.min(height - block_y) {
+                for x in 0..4.min(width - block_x) {
+                    let mut color = colors[y * 4 + x];
+                    if let Some(alpha) = alpha {
+                        color.a = alpha[y * 4 + x];
+                    }
+                    texture[[block_y + y, block_x + x]] = color;
+                }
+            }
+        }
+    }
+    Ok(texture)
+}
+
+fn rgb565(bytes: u16) -> Color {
+    Color {
+        r: ((bytes >> 11 & 0b00011111) as f32 * 255.0 / 31.0).round() as _,
+        g: ((bytes >> 5 & 0b00111111) as f32 * 255.0 / 63.0).round() as _,
+        b: ((bytes & 0b00011111) as f32 * 255.0 / 31.0).round() as _,
+        a: 0xFF,
+    }
+}
+
+fn mix(a: u8, b: u8, a_weight: u32, b_weight: u32) -> u8 {
+    ((a as u32 * a_weight + b as u32 * b_weight) / (a_weight + b_weight)) as _
+}
+
+// 2 colors, and 2 bits per pixel to pick between them or 2 colors in between
+fn read_colors<T: Read>(reader: &mut T, dxt1: bool) -> io::Result<[Color; 16]> {
+    let c0 = reader.read_u16::<LE>()?;
+    let c1 = reader.read_u16::<LE>()?;
+    let indexes = reader.read_u32::<LE>()?;
+
+    let (a, b) = (rgb565(c0), rgb565(c1));
+    let mix = |a_weight, b_weight| Color {
+        r: mix(a.r, b.r, a_weight, b_weight),
+        g: mix(a.g, b.g, a_weight, b_weight),
+        b: mix(a.b, b.b, a_weight, b_weight),
+        a: 0xFF,
+    };
+    // DXT1 has a mode with 1 color in between and transparency
+    let palette = if c0 > c1 || !dxt1 {
+        [a, b, mix(2, 1), mix(1, 2)]
+    } else {
+        [a, b, mix(1, 1), Color::default()]
+    };
+
+    let mut colors = [Color::default(); 16];
+    for (i, color) in colors.iter_mut().enumerate() {
+        *color = palette[(indexes >> (i * 2) & 0b11) as usize];
+    }
+    Ok(colors)
+}
+
+// 4 bits of alpha per pixel
+fn read_explicit_alpha<T: Read>(reader: &mut T) -> io::Result<[u8; 16]> {
+    let bits = reader.read_u64::<LE>()?;
+    let mut alpha = [0; 16];
+    for (i, a) in alpha.iter_mut().enumerate() {
+        *a = (bits >> (i * 4) & 0b1111) as u8 * 17;
+    }
+    Ok(alpha)
+}
+
+// 2 alphas, and 3 bits per pixel to pick between them or alphas in between
+fn read_interpolated_alpha<T: Read>(reader: &mut T) -> io::Result<[u8; 16]> {
+    let a0 = reader.read_u8()?;
+    let a1 = reader.read_u8()?;
+    let bits = reader.read_u48::<LE>()?;
+
+    let mut palette = [0; 8];
+    palette[0] = a0;
+    palette[1] = a1;
+    if a0 > a1 {
+        for (i, a) in palette.iter_mut().enumerate().skip(2) {
+            *a = mix(a0, a1, 8 - i as u32, i as u32 - 1);
+        }
+    } else {
+        for (i, a) in palette.iter_mut().enumerate().skip(2).take(4) {
+            *a = mix(a0, a1, 6 - i as u32, i as u32 - 1);
+        }
+        palette[6] = 0x00;
+        palette[7] = 0xFF;
+    }
+
+    let mut alpha = [0; 16];
+    for (i, a) in alpha.iter_mut().enumerate() {
+        *a = palette[(bits >> (i * 3) & 0b111) as usize];
+    }
+    Ok(alpha)
+}
+
+/// encode the texture as blocks, repeating the edge pixels of blocks past the edge
+pub fn encode<T: Write>(
+    writer: &mut T,
+    texture: &Array2<Color>,
+    compression: Compression,
+) -> io::Result<()> {
+    let (height, width) = texture.dim();
+    for block_y in (0..height).step_by(4) {
+        for block_x in (0..width).step_by(4) {
+            // repeat the edge of small textures to fill the block
+            let mut block = [Color::default(); 16];
+            for (i, color) in block.iter_mut().enumerate() {
+                let y = (block_y + i / 4).min(height - 1);
+                let x = (block_x + i % 4).min(width - 1);
+                *color = texture[[y, x]];
+            }
+
+            match compression {
+                Compression::Dxt1 => (),
+                Compression::Dxt3 => write_explicit_alpha(writer, &block)?,
+                Compression::Dxt5 => write_interpolated_alpha(writer, &block)?,
+            }
+            write_colors(writer, &block, compression == Compression::Dxt1)?;
+        }
+    }
+    Ok(())
+}
+
+fn to_rgb565(color: &Color) -> u16 {
+    let r = (color.r as f32 / 255.0 * 31.0).round() as u16;
+    let g = (color.g as f32 / 255.0 * 63.0).round() as u16;
+    let b = (color.b as f32 / 255.0 * 31.0).round() as u16;
+    r << 11 | g << 5 | b
+}
+
+fn distance_squared(a: &Color, b: &Color) -> u32 {
+    let channel = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    channel(a.r, b.r) + channel(a.g, b.g) + channel(a.b, b.b)
+}
+
+// use the two colors furthest apart as the ends, then pick the closest color
+// for each pixel
+fn write_colors<T: Write>(writer: &mut T, block: &[Color; 16], dxt1: bool) -> io::Result<()> {
+    // DXT1 can only do transparency with 1 color in between
+    const ALPHA_THRESHOLD: u8 = 0x80;
+    let transparent = dxt1 && block.iter().any(|c| c.a < ALPHA_THRESHOLD);
+    let opaque = block
+        .iter()
+        .filter(|c| !transparent || c.a >= ALPHA_THRESHOLD)
+        .collect::<Vec<_>>();
+
+    let (mut c0, mut c1) = (0, 0);
+    let mut furthest = 0;
+    for (i, a) in opaque.iter().enumerate() {
+        for b in opaque[i..].iter() {
+            let distance = distance_squared(a, b);
+            if distance >= furthest {
+                furthest = distance;
+                (c0, c1) = (to_rgb565(a), to_rgb565(b));
+            }
+        }
+    }
+    // the order of the ends picks the mode
+    if (c0 < c1) != transparent {
+        std::mem::swap(&mut c0, &mut c1);
+    }
+
+    let (a, b) = (rgb565(c0), rgb565(c1));
+    let mix = |a_weight, b_weight| Color {
+        r: mix(a.r, b.r, a_weight, b_weight),
+        g: mix(a.g, b.g, a_weight, b_weight),
+        b: mix(a.b, b.b, a_weight, b_weight),
+        a: 0xFF,
+    };
+    let palette = if transparent {
+        vec![a, b, mix(1, 1)]
+    } else {
+        vec![a, b, mix(2, 1), mix(1, 2)]
+    };
+
+    let mut indexes = 0;
+    if c0 != c1 || transparent {
+        for (i, color) in block.iter().enumerate() {
+            let index = if transparent && color.a < ALPHA_THRESHOLD {
+                3
+            } else {
+                (0..palette.len())
+                    .min_by_key(|j| distance_squared(color, &palette[*j]))
+                    .unwrap()
+            };
+            indexes |= (index as u32) << (i * 2);
+        }
+    }
+
+    writer.write_u16::<LE>(c0)?;
+    writer.write_u16::<LE>(c1)?;
+    writer.write_u32::<LE>(indexes)
+}
+
+fn write_explicit_alpha<T: Write>(writer: &mut T, block: &[Color; 16]) -> io::Result<()> {
+    let mut bits = 0;
+    for (i, color) in block.iter().enumerate() {
+        bits |= ((color.a as u64 + 8) / 17) << (i * 4);
+    }
+    writer.write_u64::<LE>(bits)
+}
+
+fn write_interpolated_alpha<T: Write>(writer: &mut T, block: &[Color; 16]) -> io::Result<()> {
+    let a0 = block.iter().map(|c| c.a).max().unwrap();
+    let a1 = block.iter().map(|c| c.a).min().unwrap();
+
+    let mut palette = [a0; 8];
+    palette[1] = a1;
+    for (i, a) in palette.iter_mut().enumerate().skip(2) {
+        *a = mix(a0, a1, 8 - i as u32, i as u32 - 1);
+    }
+
+    let mut bits = 0;
+    for (i, color) in block.iter().enumerate() {
+        let index = (0..8)
+            .min_by_key(|j| (palette[*j] as i32 - color.a as i32).abs())
+            .unwrap();
+        bits |= (index as u64) << (i * 3);
+    }
+
+    writer.write_u8(a0)?;
+    writer.write_u8(a1)?;
+    writer.write_u48::<LE>(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Color = Color {
+        r: 0xFF,
+        g: 0,
+        b: 0,
+        a: 0xFF,
+    };
+    const BLUE: Color = Color {
+        r: 0,
+        g: 0,
+        b: 0xFF,
+        a: 0xFF,
+    };
+
+    fn round_trip(texture: &Array2<Color>, compression: Compression) -> Array2<Color> {
+        let mut data = Vec::new();
+        encode(&mut data, texture, compression).unwrap();
+        let (height, width) = texture.dim();
+        decode(&mut data.as_slice(), width, height, compression).unwrap()
+    }
+
+    // pixels of red and blue, which rgb565 stores exactly
+    fn checkers(width: usize, height: usize, alpha: impl Fn(usize) -> u8) -> Array2<Color> {
+        Array2::from_shape_fn((height, width), |(y, x)| Color {
+            a: alpha(y * width + x),
+            ..if (x + y) % 2 == 0 { RED } else { BLUE }
+        })
+    }
+
+    // 2 bits per pixel, the first pixel in the lowest bits
+    fn indexes(indexes: &[u32]) -> u32 {
+        indexes
+            .iter()
+            .enumerate()
+            .map(|(i, index)| index << (i * 2))
+            .sum()
+    }
+
+    #[test]
+    fn round_trips_exact_colors() {
+        let opaque = checkers(8, 8, |_| 0xFF);
+        assert_eq!(round_trip(&opaque, Compression::Dxt1), opaque);
+        // 4 bits of alpha
+        let explicit = checkers(8, 8, |i| (i % 16) as u8 * 17);
+        assert_eq!(round_trip(&explicit, Compression::Dxt3), explicit);
+        // 2 alphas per block
+        let interpolated = checkers(8, 8, |i| if i % 3 == 0 { 0x20 } else { 0xE0 });
+        assert_eq!(round_trip(&interpolated, Compression::Dxt5), interpolated);
+    }
+
+    #[test]
+    fn round_trips_textures_smaller_than_a_block() {
+        let texture = checkers(2, 3, |_| 0xFF);
+        assert_eq!(round_trip(&texture, Compression::Dxt1), texture);
+    }
+
+    #[test]
+    fn decodes_dxt1_4_color_block() {
+        let mut block = Vec::new();
+        block.extend_from_slice(&0xF800u16.to_le_bytes());
+        block.extend_from_slice(&0x001Fu16.to_le_bytes());
+        block.extend_from_slice(&indexes(&[0, 1, 2, 3]).to_le_bytes());
+        let texture = decode(&mut block.as_slice(), 4, 4, Compression::Dxt1).unwrap();
+        let row = texture.row(0).to_vec();
+        let purple = |r, b| Color {
+            r,
+            g: 0,
+            b,
+            a: 0xFF,
+        };
+        assert_eq!(row, [RED, BLUE, purple(170, 85), purple(85, 170)]);
+    }
+
+    #[test]
+    fn decodes_dxt1_transparent_block() {
+        // the ends in increasing order pick 1 color in between and transparency
+        let mut block = Vec::new();
+        block.extend_from_slice(&0x001Fu16.to_le_bytes());
+        block.extend_from_slice(&0xF800u16.to_le_bytes());
+        block.extend_from_slice(&indexes(&[0, 1, 2, 3]).to_le_bytes());
+        let texture = decode(&mut block.as_slice(), 4, 4, Compression::Dxt1).unwrap();
+        let row = texture.row(0).to_vec();
+        let purple = Color {
+            r: 127,
+            g: 0,
+            b: 127,
+            a: 0xFF,
+        };
+        assert_eq!(row, [BLUE, RED, purple, Color::default()]);
+    }
+
+    #[test]
+    fn encodes_dxt1_transparency_with_ends_in_increasing_order() {
+        let texture = checkers(4, 4, |i| if i < 4 { 0 } else { 0xFF });
+        let mut block = Vec::new();
+        encode(&mut block, &texture, Compression::Dxt1).unwrap();
+        let c0 = u16::from_le_bytes([block[0], block[1]]);
+        let c1 = u16::from_le_bytes([block[2], block[3]]);
+        assert!(c0 <= c1);
+
+        let decoded = decode(&mut block.as_slice(), 4, 4, Compression::Dxt1).unwrap();
+        for (color, decoded) in texture.iter().zip(decoded.iter()) {
+            if color.a == 0 {
+                assert_eq!(decoded.a, 0);
+            } else {
+                assert_eq!(decoded, color);
+            }
+        }
+    }
+
+    #[test]
+    fn encodes_opaque_dxt1_with_ends_in_decreasing_order() {
+        let texture = checkers(4, 4, |_| 0xFF);
+        let mut block = Vec::new();
+        encode(&mut block, &texture, Compression::Dxt1).unwrap();
+        let c0 = u16::from_le_bytes([block[0], block[1]]);
+        let c1 = u16::from_le_bytes([block[2], block[3]]);
+        assert!(c0 > c1);
+    }
+
+    // a block whose first 8 pixels use each alpha of the palette, and the rest alpha 0
+    fn dxt5_block(a0: u8, a1: u8) -> Vec<u8> {
+        let bits = (0..8u64).map(|i| i << (i * 3)).sum::<u64>();
+        let mut block = vec![a0, a1];
+        block.extend_from_slice(&bits.to_le_bytes()[..6]);
+        block.extend_from_slice(&0xF800u16.to_le_bytes());
+        block.extend_from_slice(&0xF800u16.to_le_bytes());
+        block.extend_from_slice(&0u32.to_le_bytes());
+        block
+    }
+
+    fn alphas(block: &[u8]) -> Vec<u8> {
+        let mut reader = block;
+        let texture = decode(&mut reader, 4, 4, Compression::Dxt5).unwrap();
+        texture.iter().take(8).map(|color| color.a).collect()
+    }
+
+    #[test]
+    fn decodes_dxt5_8_alpha_palette() {
+        assert_eq!(
+            alphas(&dxt5_block(0xFF, 0)),
+            [0xFF, 0, 218, 182, 145, 109, 72, 36]
+        );
+    }
+
+    #[test]
+    fn decodes_dxt5_6_alpha_palette() {
+        // the ends in increasing order have 4 alphas in between, and 0 and 0xFF
+        assert_eq!(
+            alphas(&dxt5_block(0, 0xFF)),
+            [0, 0xFF, 51, 102, 153, 204, 0, 0xFF]
+        );
+    }
+}
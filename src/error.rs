@@ -0,0 +1,181 @@
+use crate::vec::Vec2;
+use std::{fmt, io, path::PathBuf};
+
+/// result of everything that reads or writes files
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// which texture or model something went wrong with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Index {
+    /// a texture of the first texture format bank
+    Texture(usize),
+    /// a texture of a texture format bank after the first one
+    BankTexture(usize, usize),
+    /// a model, counting only models with triangle strips
+    Model(usize),
+}
+
+impl fmt::Display for Index {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Index::Texture(i) => write!(f, "texture {}", i),
+            Index::BankTexture(bank, i) => write!(f, "bank {} texture {}", bank, i),
+            Index::Model(i) => write!(f, "model {}", i),
+        }
+    }
+}
+
+// "texture 3: ", or nothing if the index is not known
+fn prefix(index: &Option<Index>) -> String {
+    index.map_or_else(String::new, |index| format!("{}: ", index))
+}
+
+/// offsets are in bytes from the start of the file, or of the DDS file inside a ddm file.
+/// index is which texture or model it is about, if it is known
+#[derive(Debug, thiserror::Error)]
+// the fields are what the messages say they are
+#[allow(missing_docs)]
+pub enum Error {
+    /// reading or writing a file failed
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// an image could not be read or written
+    #[error(transparent)]
+    Image(#[from] image::ImageError),
+    /// an indexed png could not be read
+    #[error(transparent)]
+    PngDecoding(#[from] png::DecodingError),
+    /// an indexed png could not be written
+    #[error(transparent)]
+    PngEncoding(#[from] png::EncodingError),
+    /// a header did not start with its signature
+    #[error("{}bad {what} header {found:#010x} at {offset:#x}, expected {expected:#010x}", prefix(.index))]
+    BadHeader {
+        offset: u64,
+        index: Option<Index>,
+        what: &'static str,
+        expected: u32,
+        found: u32,
+    },
+    /// a texture is stored in a way this can not read or write
+    #[error("{}unsupported encoding {encoding} at {offset:#x}", prefix(.index))]
+    UnsupportedEncoding {
+        offset: u64,
+        index: Option<Index>,
+        encoding: String,
+    },
+    /// a replacement has more colors than its palette can hold
+    #[error("{}{colors} colors do not fit in a palette of {max} at {offset:#x}, try --quantize", prefix(.index))]
+    TooManyColors {
+        offset: u64,
+        index: Option<Index>,
+        colors: usize,
+        max: usize,
+    },
+    /// a replacement is not the size of what it replaces
+    #[error("{}size {found} does not match the original size {expected} at {offset:#x}", prefix(.index))]
+    SizeMismatch {
+        offset: u64,
+        index: Option<Index>,
+        expected: Vec2<u32>,
+        found: Vec2<u32>,
+    },
+    /// a texture is a size this can not read or write
+    #[error("{}size {size} can not be stored as a {pixel_encoding}-bit texture at {offset:#x}", prefix(.index))]
+    UnsupportedSize {
+        offset: u64,
+        index: Option<Index>,
+        size: Vec2<u32>,
+        pixel_encoding: u8,
+    },
+    /// a replacement does not fit where the original was
+    #[error("{}needs {len:#x} bytes, but only {space:#x} bytes fit at {offset:#x}", prefix(.index))]
+    TooLarge {
+        offset: u64,
+        index: Option<Index>,
+        len: usize,
+        space: usize,
+    },
+    /// a replacement has partly transparent pixels, and --alpha reject was given
+    #[error("{}{pixels} pixels are partly transparent, but the 16-bit palette at {offset:#x} only has opaque and transparent, try --alpha", prefix(.index))]
+    PartialAlpha {
+        offset: u64,
+        index: Option<Index>,
+        pixels: usize,
+    },
+    /// a texture format says its texture is past the end of the file
+    #[error("{}texture at {offset:#x} ends at {end:#x}, past the end of the file at {len:#x}", prefix(.index))]
+    OutOfFile {
+        offset: u64,
+        index: Option<Index>,
+        end: u64,
+        len: u64,
+    },
+    /// a texture format says its texture is where something else already is
+    #[error("{}texture at {offset:#x} overlaps the texture formats or texture at {other:#x}", prefix(.index))]
+    Overlap {
+        offset: u64,
+        index: Option<Index>,
+        other: u64,
+    },
+    /// a texture format whose texture would be estimated from a rejected one
+    #[error("{}texture format at {offset:#x} does not say where its texture is, and a texture format before it was rejected", prefix(.index))]
+    AfterRejected { offset: u64, index: Option<Index> },
+    /// a resized texture would be uploaded over something else in GS memory
+    #[error("{}size {size} would be uploaded {what}, for the texture format at {offset:#x}", prefix(.index))]
+    GsOverlap {
+        offset: u64,
+        index: Option<Index>,
+        size: Vec2<u32>,
+        what: String,
+    },
+    /// a written file did not read back as it was written
+    #[error("{}verification failed at {offset:#x}: {what}", prefix(.index))]
+    Unverified {
+        offset: u64,
+        index: Option<Index>,
+        what: String,
+    },
+    /// a replacement model uses a material that no material record in the file sets
+    #[error("{}material {material} has no material record in the file to copy", prefix(.index))]
+    NoMaterialRecord { index: Option<Index>, material: u32 },
+    /// a replacement is for a texture or model that does not exist
+    #[error("there is no {index}")]
+    NotFound { index: Index },
+    /// a replacement file name does not say what it replaces
+    #[error("{} should be named <stem>-<i> or <stem>-<bank>-<i>", .path.display())]
+    InvalidName { path: PathBuf },
+    /// a file name is not valid UTF-8
+    #[error("{} is not a valid UTF-8 file name", .path.display())]
+    NonUtf8Name { path: PathBuf },
+}
+
+impl Error {
+    /// set which texture or model this is about, if it is not known yet
+    pub fn with_index(mut self, new: Index) -> Self {
+        match &mut self {
+            Error::BadHeader { index, .. }
+            | Error::UnsupportedEncoding { index, .. }
+            | Error::TooManyColors { index, .. }
+            | Error::SizeMismatch { index, .. }
+            | Error::UnsupportedSize { index, .. }
+            | Error::TooLarge { index, .. }
+            | Error::PartialAlpha { index, .. }
+            | Error::OutOfFile { index, .. }
+            | Error::Overlap { index, .. }
+            | Error::AfterRejected { index, .. }
+            | Error::NoMaterialRecord { index, .. }
+            | Error::GsOverlap { index, .. }
+            | Error::Unverified { index, .. } => {
+                index.get_or_insert(new);
+            }
+            _ => (),
+        }
+        self
+    }
+
+    /// the end of the file was reached
+    pub fn is_eof(&self) -> bool {
+        matches!(self, Error::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof)
+    }
+}
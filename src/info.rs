@@ -0,0 +1,141 @@
+use crate::{
+    error::Result,
+    model::{Models, NormalMode},
+    texture::{texture_format::TextureFormats, texture_header::Upload},
+};
+use itertools::Itertools;
+use serde_json::{json, Value};
+use std::{ops::Range, path::Path};
+
+// offsets are written in hex, like a hex editor shows them
+fn hex(offset: impl Into<u64>) -> String {
+    format!("{:#x}", offset.into())
+}
+
+// the decoded fields of a GS upload
+fn upload(upload: &Upload) -> Value {
+    json!({
+        "dbp": upload.dbp,
+        "dbw": upload.dbw,
+        "dpsm": hex(upload.dpsm),
+        "dsax": upload.dsax,
+        "dsay": upload.dsay,
+        "rrw": upload.rrw,
+        "rrh": upload.rrh,
+        "nloop": upload.nloop,
+        "eop": upload.eop,
+        "qwc": upload.qwc,
+        "addr": hex(upload.addr),
+    })
+}
+
+/// where every texture format, texture, model and triangle strip of a binary file is,
+/// and the gaps in between that were not parsed
+pub fn report(path: &Path) -> Result<Value> {
+    let data = std::fs::read(path)?;
+    let banks = TextureFormats::read_banks(&data)?;
+    let models = Models::read(&data)?;
+
+    // everything that was parsed
+    let mut parsed = Vec::<Range<u64>>::new();
+
+    let mut bank_reports = Vec::new();
+    for (bank, tfs) in banks.iter().enumerate() {
+        let mut textures = Vec::new();
+        if let Some(first) = tfs.0.first() {
+            parsed.push(first.header as u64..tfs.end() as u64);
+        }
+        for (i, tf) in tfs.0.iter().enumerate() {
+            for range in tf.ranges() {
+                parsed.push(range.start as u64..range.end as u64);
+            }
+            textures.push(json!({
+                "index": i,
+                "offset": hex(tf.header),
+                "size": [tf.size.x, tf.size.y],
+                "color_depth": tf.color_depth,
+                "pixel_encoding": tf.pixel_encoding,
+                "double_size": tf.double_size,
+                "location": hex(tf.location),
+                "palette_location": hex(tf.palette_location),
+                "addressed": tf.addressed,
+                "len": tf.len(),
+                "psm": tf.gs.psm().map(hex),
+                "tw_th": tf.gs.tw_th().map(|tw_th| [tw_th.x, tw_th.y]),
+                "texture_upload": upload(&tf.gs.texture),
+                "palette_upload": upload(&tf.gs.palette),
+            }));
+        }
+        bank_reports.push(json!({
+            "bank": bank,
+            "offset": hex(tfs.0.first().map_or(0, |tf| tf.header)),
+            "end": hex(tfs.end()),
+            "textures": textures,
+            "rejected": tfs.1.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            "warnings": tfs.2.iter().map(|w| w.to_string()).collect::<Vec<_>>(),
+        }));
+    }
+
+    let mut model_reports = Vec::new();
+    for (i, model) in models.0.iter().enumerate() {
+        parsed.push(model.header..model.end);
+        let strips = model
+            .triangle_strips
+            .iter()
+            .map(|ts| {
+                json!({
+                    "offset": hex(ts.offset),
+                    "vertex_count": ts.pos.len(),
+                    "material": ts.material,
+                })
+            })
+            .collect::<Vec<_>>();
+        let winding = model.winding_report(&model.normals(NormalMode::Stored));
+        model_reports.push(json!({
+            "index": i,
+            "offset": hex(model.header),
+            "end": hex(model.end),
+            "vertex_count": model.triangle_strips.iter().map(|ts| ts.pos.len()).sum::<usize>(),
+            "strip_count": model.triangle_strips.len(),
+            "materials": model
+                .triangle_strips
+                .iter()
+                .map(|ts| ts.material)
+                .sorted_unstable()
+                .dedup()
+                .collect::<Vec<_>>(),
+            "winding": {
+                "triangles": winding.triangles,
+                "degenerate": winding.degenerate,
+                "parity_disagreements": winding.parity,
+                "seeded_parity_disagreements": winding.seeded_parity,
+            },
+            "triangle_strips": strips,
+        }));
+    }
+
+    // the gaps between everything that was parsed, up to the end of the file
+    parsed.push(data.len() as u64..data.len() as u64);
+    parsed.sort_unstable_by_key(|range| range.start);
+    let mut gaps = Vec::new();
+    let mut position = 0;
+    for range in parsed.iter() {
+        if range.start > position && position < data.len() as u64 {
+            let gap = &data[position as usize..(range.start as usize).min(data.len())];
+            gaps.push(json!({
+                "offset": hex(position),
+                "len": gap.len(),
+                "zeros": gap.iter().all(|b| *b == 0),
+            }));
+        }
+        position = position.max(range.end);
+    }
+
+    Ok(json!({
+        "path": path.to_string_lossy(),
+        "len": data.len(),
+        "texture_formats": bank_reports,
+        "models": model_reports,
+        "gaps": gaps,
+    }))
+}
@@ -0,0 +1,460 @@
+//! Extracts textures and models from the binary files of NiGHTS into Dreams, and imports
+//! replacements for them.
+//!
+//! - [`Textures`] and [`Models`] read from and write to `.BIN` files.
+//!   [`TextureFormats`] describes where each texture is and how it is stored.
+//! - [`DDM`] reads and writes the DDS images inside `.ddm` files.
+//! - [`texture::convert_4bit`] and [`texture::convert_8bit`] unscramble textures the way the
+//!   PS2 stores them, and [`texture::quantize`] reduces the colors of replacements.
+//! - [`Error`] says what went wrong, where in the file, and with which texture or model.
+//!
+//! ```no_run
+//! use nights_into_bins::{
+//!     model::{material::Materials, NormalMode, Winding},
+//!     Models, Textures,
+//! };
+//! use std::path::Path;
+//!
+//! let path = Path::new("DATCLARIS.BIN");
+//! let data = std::fs::read(path)?;
+//! let textures = Textures::read(&data)?;
+//! let models = Models::read(&data)?;
+//! let materials = Materials::new(&data, &models, &textures);
+//! let out = Path::new("out");
+//! models.write_to_gltf(path, out, &textures, &materials, NormalMode::Stored, Winding::Normals)?;
+//! # Ok::<(), nights_into_bins::Error>(())
+//! ```
+
+#![warn(missing_docs)]
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    io,
+    path::{Path, PathBuf},
+};
+
+use glob::Pattern;
+use itertools::Itertools;
+use model::{material::Materials, obj, NormalMode, Winding};
+use rayon::prelude::*;
+use texture::{alpha::AlphaPolicy, quantize::Dither, Texture, TextureId};
+
+/// DDS images inside ddm files
+pub mod ddm;
+/// everything that can go wrong
+pub mod error;
+/// json reports of where everything in binary files is
+pub mod info;
+/// models inside binary files
+pub mod model;
+/// textures inside binary files
+pub mod texture;
+/// small vectors
+pub mod vec;
+
+pub use ddm::DDM;
+pub use error::{Error, Result};
+pub use model::Models;
+pub use texture::{texture_format::TextureFormats, Textures};
+
+/// where files are written, and which files and parts of them are used
+pub struct Options {
+    /// extracted files go in `<out>/<stem>/`
+    pub out: PathBuf,
+    /// the Reloaded-II mod, replaced files go in `<mod_dir>/Redirector/afs/`
+    pub mod_dir: PathBuf,
+    /// print more about what is read
+    pub verbose: bool,
+    /// extract and import textures
+    pub textures: bool,
+    /// extract and import models
+    pub models: bool,
+    /// only binary and ddm files with a matching file name, every file if empty
+    pub filters: Vec<Pattern>,
+    /// reduce textures with too many colors to fit their palette instead of failing
+    pub quantize: Option<Dither>,
+    /// what happens to partly transparent pixels in 16-bit palettes
+    pub alpha: AlphaPolicy,
+    /// files extracted at the same time, 0 for one per CPU core
+    pub jobs: usize,
+    /// which normals are extracted
+    pub normals: NormalMode,
+    /// how the triangles of extracted models are wound
+    pub winding: Winding,
+    /// textures are extracted as indexed pngs with the palette they are stored with
+    pub indexed: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            out: PathBuf::from("out"),
+            mod_dir: PathBuf::from("in/nights.test.nightsintobins"),
+            verbose: false,
+            textures: true,
+            models: true,
+            filters: Vec::new(),
+            quantize: None,
+            alpha: AlphaPolicy::default(),
+            jobs: 0,
+            normals: NormalMode::default(),
+            winding: Winding::default(),
+            indexed: false,
+        }
+    }
+}
+
+impl Options {
+    /// where the replaced binary and ddm files are written
+    pub fn afs_dir(&self) -> PathBuf {
+        self.mod_dir.join("Redirector/afs")
+    }
+
+    /// whether a binary or ddm file is used. names that are not UTF-8 are matched lossily
+    pub fn matches(&self, file_path: &Path) -> bool {
+        let file_name = file_path.file_name().unwrap_or_default().to_string_lossy();
+        self.filters.is_empty() || self.filters.iter().any(|f| f.matches(&file_name))
+    }
+}
+
+/// extract the textures and models of a binary or ddm file, or of every file in a directory.
+/// files in a directory are extracted in parallel, those that fail are reported,
+/// and the rest are still extracted
+pub fn extract(file_path: &Path, options: &Options) -> Result<()> {
+    if file_path.is_dir() {
+        process_dir(file_path, options)
+    } else {
+        process_file(file_path, options)
+    }
+}
+
+fn process_dir(file_path: &Path, options: &Options) -> Result<()> {
+    let mut file_paths = Vec::new();
+    for entry in walkdir::WalkDir::new(file_path).sort_by_file_name() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                continue;
+            }
+        };
+        if entry.file_type().is_file() && options.matches(entry.path()) {
+            file_paths.push(entry.into_path());
+        }
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.jobs)
+        .build()
+        .map_err(io::Error::other)?;
+    pool.install(|| {
+        file_paths.par_iter().for_each(|file_path| {
+            if let Err(e) = process_file(file_path, options) {
+                eprintln!("Error: {}: {}", file_path.display(), e);
+            }
+        })
+    });
+    Ok(())
+}
+
+fn process_file(file_path: &Path, options: &Options) -> Result<()> {
+    if !options.matches(file_path) {
+        return Ok(());
+    }
+    let Some(ext) = file_path.extension() else {
+        return Ok(());
+    };
+    let (Some(ext), Some(stem)) = (
+        ext.to_str(),
+        file_path.file_stem().and_then(|stem| stem.to_str()),
+    ) else {
+        return Err(Error::NonUtf8Name {
+            path: file_path.to_path_buf(),
+        });
+    };
+    if ext == "BIN" {
+        println!("path: {}", file_path.display());
+        // read from bin file, once
+        let data = std::fs::read(file_path)?;
+        let models = if options.models {
+            Models::read(&data)?
+        } else {
+            Models(Vec::new())
+        };
+        let textures = if options.textures {
+            Textures::read(&data)?
+        } else {
+            Textures::default()
+        };
+        print_texture_formats(stem, &textures.1, options.verbose);
+        // triangles the winding strategies do not agree on
+        for (i, model) in models.0.iter().enumerate() {
+            let report = model.winding_report(&model.normals(options.normals));
+            if report.parity > 0 || report.seeded_parity > 0 {
+                println!(
+                    "- {}-{}: {} triangles, {} wound differently by parity than by normals, \
+                    {} by seeded parity, {} degenerate skipped",
+                    stem,
+                    i,
+                    report.triangles,
+                    report.parity,
+                    report.seeded_parity,
+                    report.degenerate
+                );
+            }
+        }
+        // write to obj files
+        models.write_to_obj(file_path, &options.out, options.normals, options.winding)?;
+        let materials = Materials::new(&data, &models, &textures);
+        if !models.0.is_empty() {
+            // without the textures, every material would be unmapped
+            if options.textures {
+                for (id, material) in materials.unmapped() {
+                    println!(
+                        "- {}-{}: no texture for this material, used by models {}",
+                        stem,
+                        id,
+                        material.models.iter().join(", ")
+                    );
+                }
+                for (id, material) in materials.unmatched() {
+                    println!(
+                        "- {}-{}: no material record points at a texture, \
+                        drawn with the texture of the same index, used by models {}",
+                        stem,
+                        id,
+                        material.models.iter().join(", ")
+                    );
+                }
+            }
+            materials.write_to_mtl(file_path, &options.out)?;
+        }
+        // write to glb file
+        models.write_to_gltf(
+            file_path,
+            &options.out,
+            &textures,
+            &materials,
+            options.normals,
+            options.winding,
+        )?;
+        // write to png files
+        textures.write_to_image(file_path, &options.out, options.indexed)?;
+    } else if ext == "ddm" && options.textures {
+        println!("path: {}", file_path.display());
+        // read from ddm file
+        let ddm = DDM::read(file_path)?;
+        // write to png files, or dds files if they can not be decoded
+        for (i, dds) in ddm.entries.iter().enumerate() {
+            if let Ok(header) = dds.header() {
+                println!(
+                    "- {}-{}: size: [{}, {}], format: {:?}, mip maps: {}",
+                    stem, i, header.width, header.height, header.pixel_format, header.mip_map_count
+                );
+            }
+            if let Err(e) = dds.write_to_image(file_path, &options.out, i) {
+                println!("- {}-{}: {}, writing dds file instead", stem, i, e);
+                dds.write(file_path, &options.out, i)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// the texture formats that were rejected, what is worth knowing about the ones that were not,
+// and, if verbose, every one of them
+fn print_texture_formats(stem: &str, banks: &[TextureFormats], verbose: bool) {
+    for e in banks.iter().flat_map(|tfs| &tfs.1) {
+        println!("- {}: texture format rejected, {}", stem, e);
+    }
+    for warning in banks.iter().flat_map(|tfs| &tfs.2) {
+        eprintln!("{}", warning);
+    }
+    if verbose {
+        for tfs in banks {
+            println!("Texture formats:");
+            for (i, tf) in tfs.0.iter().enumerate() {
+                println!("- Texture format:");
+                println!(
+                    "    size: [{}, {}], color_depth: {}, pixel_encoding: {}, location: {:#x}, palette_location: {:#x}, index: {}",
+                    tf.size.x, tf.size.y, tf.color_depth, tf.pixel_encoding, tf.location, tf.palette_location, i
+                );
+            }
+        }
+    }
+}
+
+/// print a json report of where everything in a binary file is
+pub fn info(file_path: &Path) -> Result<()> {
+    let report = info::report(file_path)?;
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report).map_err(io::Error::from)?
+    );
+    Ok(())
+}
+
+/// copy binary and ddm files into the mod, with the textures and models in the replacement
+/// directory. replacements that fail are reported, and the rest are still imported
+pub fn import(binary_path: &Path, replacement_path: &Path, options: &Options) -> Result<()> {
+    let afs_dir = options.afs_dir();
+    let mut replacement_textures = HashMap::<PathBuf, Textures>::new();
+    let mut replacement_dds = HashMap::<PathBuf, DDM>::new();
+    let mut replacement_models = HashMap::new();
+    for entry in walkdir::WalkDir::new(replacement_path) {
+        let entry = entry.map_err(io::Error::from)?;
+        let file_path = entry.path();
+        if let Some(ext) = file_path.extension() {
+            let (Some(ext), Some(stem)) = (
+                ext.to_str(),
+                file_path.file_stem().and_then(|stem| stem.to_str()),
+            ) else {
+                eprintln!(
+                    "Error: {}",
+                    Error::NonUtf8Name {
+                        path: file_path.to_path_buf()
+                    }
+                );
+                continue;
+            };
+            if ext == "obj" && options.models {
+                let binary_file = PathBuf::from(stem).with_extension("BIN");
+                if !options.matches(&binary_file) {
+                    continue;
+                }
+                // read replacement models
+                println!("path: {}", file_path.display());
+                match obj::read(file_path) {
+                    Ok(models) => {
+                        replacement_models.insert(binary_file, models);
+                    }
+                    Err(e) => eprintln!("Error: {}: {}", file_path.display(), e),
+                }
+            } else if (ext == "png" || ext == "dds") && options.textures {
+                // <stem>-<i> for the first bank, <stem>-<bank>-<i> for later banks
+                let Some((binary_file_stem, id)) = stem
+                    .split_once('-')
+                    .and_then(|(stem, id)| Some((stem, TextureId::parse(id)?)))
+                else {
+                    eprintln!(
+                        "Error: {}",
+                        Error::InvalidName {
+                            path: file_path.to_path_buf()
+                        }
+                    );
+                    continue;
+                };
+
+                // replacements for ddm files can also be dds files
+                let ddm_file = PathBuf::from(binary_file_stem).with_extension("ddm");
+                let binary_file = PathBuf::from(binary_file_stem).with_extension("BIN");
+                let is_ddm = binary_path.join(&ddm_file).exists();
+                if !options.matches(if is_ddm { &ddm_file } else { &binary_file }) {
+                    continue;
+                }
+                // read replacement textures
+                println!("path: {}", file_path.display());
+                if is_ddm {
+                    let ddm = match replacement_dds.entry(ddm_file) {
+                        Entry::Occupied(entry) => entry.into_mut(),
+                        Entry::Vacant(entry) => {
+                            let ddm_path = binary_path.join(entry.key());
+                            match DDM::read(&ddm_path) {
+                                Ok(ddm) => entry.insert(ddm),
+                                Err(e) => {
+                                    eprintln!("Error: {}: {}", ddm_path.display(), e);
+                                    continue;
+                                }
+                            }
+                        }
+                    };
+                    // ddm files have no banks
+                    let replaced = if id.bank == 0 {
+                        ddm.replace(id.index, file_path)
+                    } else {
+                        Err(Error::InvalidName {
+                            path: file_path.to_path_buf(),
+                        })
+                    };
+                    if let Err(e) = replaced {
+                        eprintln!("Error: {}: {}", file_path.display(), e);
+                    }
+                } else if ext == "png" {
+                    match Texture::read_from_image(file_path, true) {
+                        Ok(texture) => {
+                            replacement_textures
+                                .entry(binary_file)
+                                .or_default()
+                                .0
+                                .insert(id, texture);
+                        }
+                        Err(e) => eprintln!("Error: {}: {}", file_path.display(), e),
+                    }
+                }
+            }
+        }
+    }
+    // write replacement texture
+    let mut failed = Vec::new();
+    let mut summary = Vec::new();
+    for (binary_file_stem, textures) in replacement_textures.iter() {
+        let original = binary_path.join(binary_file_stem);
+        match textures.write_to_bin(&original, &afs_dir, options.quantize, options.alpha) {
+            Ok(report) => {
+                let stem = binary_file_stem.file_stem().unwrap().to_str().unwrap();
+                print_texture_formats(stem, &report.banks, options.verbose);
+                for (id, notice) in report.notices.iter() {
+                    println!("- {}-{}: {}", stem, id, notice);
+                }
+                summary.push((binary_file_stem, report.alpha));
+            }
+            Err(e) => {
+                eprintln!("Error: {}: {}", original.display(), e);
+                failed.push(binary_file_stem.clone());
+            }
+        }
+    }
+    // write replacement models, into the copy if its textures were replaced
+    for (binary_file_stem, models) in replacement_models {
+        let original = if replacement_textures.contains_key(&binary_file_stem)
+            && !failed.contains(&binary_file_stem)
+        {
+            afs_dir.join(&binary_file_stem)
+        } else {
+            binary_path.join(&binary_file_stem)
+        };
+        match Models::write_to_bin(&models, &original, &afs_dir) {
+            Ok(reports) => {
+                let stem = binary_file_stem.file_stem().unwrap().to_str().unwrap();
+                for (i, report) in reports {
+                    println!("- {}-{}: {}", stem, i, report);
+                }
+            }
+            Err(e) => eprintln!("Error: {}: {}", original.display(), e),
+        }
+    }
+    for (ddm_file, ddm) in replacement_dds {
+        let original = binary_path.join(ddm_file);
+        if let Err(e) = ddm.write_to_ddm(&original, &afs_dir) {
+            eprintln!("Error: {}: {}", original.display(), e);
+        }
+    }
+
+    // which binary files were verified, and how the alpha of each replaced texture was stored
+    if !summary.is_empty() || !failed.is_empty() {
+        println!("summary:");
+    }
+    summary.sort_unstable_by_key(|(binary_file_stem, _)| *binary_file_stem);
+    for (binary_file_stem, reports) in summary {
+        println!("- {}: verified", binary_file_stem.display());
+        let stem = binary_file_stem.file_stem().unwrap().to_str().unwrap();
+        for (id, report) in reports {
+            println!("- {}-{}: {}", stem, id, report);
+        }
+    }
+    for binary_file_stem in failed {
+        println!("- {}: failed", binary_file_stem.display());
+    }
+
+    Ok(())
+}
@@ -1,58 +1,165 @@
-use std::{
-    collections::HashMap,
-    env,
-    io::{Read, Write},
-    path::{Path, PathBuf},
+//! the command line, which parses arguments into [`nights_into_bins::Options`] and runs them
+
+use clap::{Args, Parser, Subcommand};
+use glob::Pattern;
+use nights_into_bins::{
+    model::{NormalMode, Winding},
+    texture::{alpha::AlphaPolicy, quantize::Dither},
+    Options,
 };
+use std::path::{Path, PathBuf};
 
-use texture::Texture;
+/// Extracts textures and models from the binary files of NiGHTS into Dreams, and imports
+/// replacements for them
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
 
-use crate::{ddm::DDM, model::Models, texture::Textures};
+#[derive(Subcommand)]
+enum Command {
+    /// Extract textures and models from a binary or ddm file, or every file in a directory
+    Extract {
+        /// Binary or ddm file, or directory of them
+        path: PathBuf,
+        /// Directory to extract into, each file gets its own directory
+        #[arg(long, default_value = "out")]
+        out: PathBuf,
+        /// Files of a directory extracted at the same time, 0 for one per CPU core
+        #[arg(short, long, default_value_t = 0)]
+        jobs: usize,
+        /// Normals to extract: stored, renormalize (to unit length, recomputing the ones with no
+        /// length), or recompute (smooth normals from the triangles)
+        #[arg(long, value_name = "MODE", default_value = "stored", value_parser = parse_normals)]
+        normals: NormalMode,
+        /// How triangles of extracted models are wound: normals (facing the same way as their
+        /// normals), parity (alternating), or seeded-parity (alternating, starting with the
+        /// first triangle the normals say something about)
+        #[arg(long, value_name = "STRATEGY", default_value = "normals", value_parser = parse_winding)]
+        winding: Winding,
+        /// Extract textures as indexed PNGs with the palette they are stored with, importing
+        /// them keeps the palette and indexes exactly
+        #[arg(long)]
+        indexed: bool,
+        #[command(flatten)]
+        filter: Filter,
+    },
+    /// Copy binary and ddm files into the mod and replace their textures and models
+    Import {
+        /// Directory with the original binary and ddm files
+        binary_dir: PathBuf,
+        /// Directory with the replacement png, dds and obj files
+        replacement_dir: PathBuf,
+        /// Mod directory, the copies go in its Redirector/afs/ directory
+        #[arg(long, default_value = "in/nights.test.nightsintobins")]
+        mod_dir: PathBuf,
+        /// Reduce textures with too many colors instead of failing, optionally dithering them
+        #[arg(
+            long,
+            value_name = "DITHER",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "none",
+            value_parser = parse_dither,
+        )]
+        quantize: Option<Dither>,
+        /// What happens to partly transparent pixels in 16-bit palettes: threshold, an alpha
+        /// to threshold at (threshold is 128), dither, or reject
+        #[arg(long, value_name = "POLICY", default_value = "threshold", value_parser = parse_alpha)]
+        alpha: AlphaPolicy,
+        #[command(flatten)]
+        filter: Filter,
+    },
+    /// Print a json report of where everything in a binary file is
+    Info {
+        /// Binary file
+        path: PathBuf,
+    },
+    /// Extract the DDS images of a ddm file, or replace them with the images in a directory
+    Ddm {
+        /// Ddm file
+        path: PathBuf,
+        /// Directory with the replacement png and dds files
+        replacement_dir: Option<PathBuf>,
+        /// Directory to extract into
+        #[arg(long, default_value = "out")]
+        out: PathBuf,
+        /// Mod directory, the copy goes in its Redirector/afs/ directory
+        #[arg(long, default_value = "in/nights.test.nightsintobins")]
+        mod_dir: PathBuf,
+    },
+}
 
-mod ddm;
-mod model;
-mod texture;
-mod vec;
+#[derive(Args)]
+struct Filter {
+    /// Print more about what is read
+    #[arg(short, long)]
+    verbose: bool,
+    /// Only textures, not models
+    #[arg(long, conflicts_with = "only_models")]
+    only_textures: bool,
+    /// Only models, not textures
+    #[arg(long)]
+    only_models: bool,
+    /// Only binary and ddm files with a matching file name, like 'DATCLARIS*.BIN'
+    #[arg(long = "filter", value_name = "GLOB")]
+    filters: Vec<Pattern>,
+}
 
-fn process_dir(file_path: &Path) -> std::io::Result<()> {
-    for entry in std::fs::read_dir(file_path)? {
-        let file_path = entry?.path();
-        process_file(&file_path)?;
+impl Filter {
+    fn options(self) -> Options {
+        Options {
+            verbose: self.verbose,
+            textures: !self.only_models,
+            models: !self.only_textures,
+            filters: self.filters,
+            ..Options::default()
+        }
     }
-    Ok(())
 }
 
-fn process_file(file_path: &Path) -> std::io::Result<()> {
-    if file_path.is_dir() {
-        process_dir(file_path)?;
-    } else if let Some(ext) = file_path.extension() {
-        let ext = ext.to_str().unwrap();
-        if ext == "BIN" {
-            println!("path: {}", file_path.to_str().unwrap());
-            // read from bin file
-            let models = Models::read_from_bin(&file_path)?;
-            let textures = Textures::read_from_bin(&file_path)?;
-            // write to obj files
-            models.write_to_obj(&file_path)?;
-            if !models.0.is_empty() {
-                textures.write_to_mtl(&file_path)?;
-            }
-            // write to png files
-            textures.write_to_image(&file_path)?;
-        } else if ext == "ddm" {
-            println!("path: {}", file_path.to_str().unwrap());
-            // read from ddm file
-            let ddm = DDM::read(&file_path)?;
-            // write to dds files
-            for (i, dds) in ddm.0.iter().enumerate() {
-                dds.write(&file_path, i)?;
-            }
-        }
+fn parse_dither(s: &str) -> Result<Dither, String> {
+    match s {
+        "none" => Ok(Dither::None),
+        "floyd-steinberg" => Ok(Dither::FloydSteinberg),
+        "ordered" => Ok(Dither::Ordered),
+        _ => Err("expected none, floyd-steinberg or ordered".to_string()),
+    }
+}
+
+fn parse_alpha(s: &str) -> Result<AlphaPolicy, String> {
+    match s {
+        "threshold" => Ok(AlphaPolicy::default()),
+        "dither" => Ok(AlphaPolicy::Dither),
+        "reject" => Ok(AlphaPolicy::Reject),
+        _ => s
+            .parse()
+            .map(AlphaPolicy::Threshold)
+            .map_err(|_| "expected threshold, 0-255, dither or reject".to_string()),
     }
-    Ok(())
 }
 
-fn main() -> std::io::Result<()> {
+fn parse_normals(s: &str) -> Result<NormalMode, String> {
+    match s {
+        "stored" => Ok(NormalMode::Stored),
+        "renormalize" => Ok(NormalMode::Renormalize),
+        "recompute" => Ok(NormalMode::Recompute),
+        _ => Err("expected stored, renormalize or recompute".to_string()),
+    }
+}
+
+fn parse_winding(s: &str) -> Result<Winding, String> {
+    match s {
+        "normals" => Ok(Winding::Normals),
+        "parity" => Ok(Winding::Parity),
+        "seeded-parity" => Ok(Winding::SeededParity),
+        _ => Err("expected normals, parity or seeded-parity".to_string()),
+    }
+}
+
+fn main() {
     // //* TEST
     // //* Create test texture
     // let mut test_pal = texture::test::palette(texture::test::Test::_4096Colors64x64);
@@ -69,54 +176,71 @@ fn main() -> std::io::Result<()> {
     // return Ok(());
     // //* END TEST
 
-    let args: Vec<String> = env::args().collect();
-
-    match args.len() {
-        2 => {
-            let file_path = Path::new(&args[1]);
-            if file_path.is_dir() {
-                process_dir(file_path)?;
-            } else {
-                process_file(file_path)?;
-            }
+    let result = match Cli::parse().command {
+        Command::Extract {
+            path,
+            out,
+            jobs,
+            normals,
+            winding,
+            indexed,
+            filter,
+        } => {
+            let options = Options {
+                out,
+                jobs,
+                normals,
+                winding,
+                indexed,
+                ..filter.options()
+            };
+            nights_into_bins::extract(&path, &options)
         }
-        3 => {
-            let binary_path = Path::new(&args[1]);
-            let replacement_path = Path::new(&args[2]);
-
-            let mut replacement_textures = HashMap::<PathBuf, Textures>::new();
-            for entry in walkdir::WalkDir::new(replacement_path) {
-                let entry = entry?;
-                let file_path = entry.path();
-                match file_path.extension() {
-                    Some(ext) => {
-                        if ext.to_str().unwrap() == "png" {
-                            // read replacement textures
-                            println!("path: {}", file_path.to_str().unwrap());
-                            let stem = file_path.file_stem().unwrap().to_str().unwrap();
-                            let seperator = stem.find('-').unwrap();
-                            let binary_file_stem = &stem[..seperator];
-                            let texture_index = stem[seperator + 1..].parse().unwrap();
-                            replacement_textures
-                                .entry(PathBuf::from(binary_file_stem).with_extension("BIN"))
-                                .or_default()
-                                .0
-                                .insert(texture_index, Texture::read_from_image(&file_path, true));
-                        }
-                    }
-                    None => (),
-                }
-            }
-            // write replacement texture
-            for (binary_file_stem, textures) in replacement_textures {
-                textures.write_to_bin(&binary_path.join(binary_file_stem))?;
-            }
+        Command::Import {
+            binary_dir,
+            replacement_dir,
+            mod_dir,
+            quantize,
+            alpha,
+            filter,
+        } => {
+            let options = Options {
+                mod_dir,
+                quantize,
+                alpha,
+                ..filter.options()
+            };
+            nights_into_bins::import(&binary_dir, &replacement_dir, &options)
         }
-        _ => {
-            eprintln!("Error. Usage:\n  ./nights_into_bins <binary_file>\n    extracts textures and models from binary files in directory and exports into ./out/\n  ./nights_into_bins <binary_file_directory>\n    extracts textures and models from binary file and exports into ./out/\n  ./nights_into_bins <binary_file_directory> <texture_replacement_file_directory>\n    copies binary files into mod at ./in/nights.test.nightsintobins/ and replaces their textures");
-            std::process::exit(1);
+        Command::Info { path } => nights_into_bins::info(&path),
+        Command::Ddm {
+            path,
+            replacement_dir,
+            out,
+            mod_dir,
+        } => {
+            // only this ddm file, the binary file directory is the one it is in
+            let file_name = path.file_name().unwrap().to_str().unwrap();
+            let options = Options {
+                out,
+                mod_dir,
+                models: false,
+                filters: vec![Pattern::new(&Pattern::escape(file_name)).unwrap()],
+                ..Options::default()
+            };
+            match replacement_dir {
+                Some(replacement_dir) => nights_into_bins::import(
+                    path.parent().unwrap_or(Path::new("")),
+                    &replacement_dir,
+                    &options,
+                ),
+                None => nights_into_bins::extract(&path, &options),
+            }
         }
-    }
+    };
 
-    Ok(())
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
 }
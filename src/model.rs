@@ -1,20 +1,42 @@
 use byteorder::{ReadBytesExt, WriteBytesExt, LE};
 use itertools::Itertools;
+use material::Materials;
 use std::{
     collections::HashMap,
+    fmt,
     fs::File,
-    io::{self, Read, Seek, SeekFrom, Write},
+    io::{self, Cursor, Read, Seek, SeekFrom, Write},
     path::Path,
 };
-use triangle_strip::TriangleStrip;
+use triangle_strip::{TriangleStrip, NEUTRAL_COLOR};
 
+use crate::{
+    error::{Error, Index, Result},
+    texture::Textures,
+    vec::Vec3,
+};
+
+/// write models to glTF binary files
+pub mod gltf;
+/// which texture each material is drawn with
+pub mod material;
+/// read replacement models from obj files
+pub mod obj;
+/// triangle strip packets of models
 pub mod triangle_strip;
 
+/// models of a binary file, in order
 pub struct Models(pub Vec<Model>);
 
 impl Models {
-    pub fn read_from_bin(path: &Path) -> io::Result<Self> {
-        let mut reader = File::open(path)?;
+    /// read every model of a binary file, skipping empty models
+    pub fn read_from_bin(path: &Path) -> Result<Self> {
+        Self::read(&std::fs::read(path)?)
+    }
+
+    /// read every model of the contents of a binary file, skipping empty models
+    pub fn read(data: &[u8]) -> Result<Self> {
+        let mut reader = Cursor::new(data);
 
         let mut models = Vec::new();
 
@@ -26,9 +48,10 @@ impl Models {
                     }
                 }
                 Err(e) => {
-                    if e.kind() == io::ErrorKind::UnexpectedEof {
+                    if e.is_eof() {
                         break;
                     }
+                    return Err(e.with_index(Index::Model(models.len())));
                 }
             }
         }
@@ -36,36 +59,267 @@ impl Models {
         Ok(Self(models))
     }
 
-    pub fn write_to_obj(&self, file_path: &Path) -> io::Result<()> {
+    /// replace models in a copy of the original file.
+    /// the triangle strips of each replacement must fit where the original ones were,
+    /// since the file is not moved around. the copy is written into out_dir.
+    /// returns how much of its space each replacement uses, by model
+    pub fn write_to_bin(
+        replacements: &HashMap<usize, Model>,
+        original: &Path,
+        out_dir: &Path,
+    ) -> Result<Vec<(usize, SpaceReport)>> {
+        // create dir if it does not exist
+        if !out_dir.exists() {
+            std::fs::create_dir_all(out_dir)?;
+        }
+
+        // read original file and models
+        let mut data = std::fs::read(original)?;
+        let models = Models::read(&data)?;
+        let mut reports = Vec::new();
+
+        // the first material record of each material in the file, for materials
+        // the replaced model did not use
+        let mut file_records = HashMap::new();
+        for model in models.0.iter() {
+            for (material, record) in material_records(&data, model) {
+                file_records.entry(material).or_insert(record);
+            }
+        }
+
+        for (i, replacement) in replacements.iter() {
+            let model = models.0.get(*i).ok_or(Error::NotFound {
+                index: Index::Model(*i),
+            })?;
+            let start = find_packets(&data, model);
+            // the model's own material records come first
+            let mut records = file_records.clone();
+            for (material, record) in material_records(&data, model).into_iter().rev() {
+                records.insert(material, record);
+            }
+
+            let mut packets = Cursor::new(Vec::new());
+            replacement
+                .write_to_bin(&mut packets, &records)
+                .map_err(|e| e.with_index(Index::Model(*i)))?;
+            let mut packets = packets.into_inner();
+            let space = model.end as usize - start;
+            if packets.len() > space {
+                return Err(Error::TooLarge {
+                    offset: start as _,
+                    index: Some(Index::Model(*i)),
+                    len: packets.len(),
+                    space,
+                });
+            }
+            reports.push((
+                *i,
+                SpaceReport {
+                    triangle_strips: replacement.triangle_strips.len(),
+                    len: packets.len(),
+                    space,
+                },
+            ));
+
+            // pad with NOPs
+            packets.resize(space, 0);
+            data[start..model.end as usize].copy_from_slice(&packets);
+
+            // write vertex count
+            let vertex_count = replacement
+                .triangle_strips
+                .iter()
+                .map(|ts| ts.pos.len() as u32)
+                .sum::<u32>();
+            let count = model.header as usize + 8;
+            data[count..count + 4].copy_from_slice(&vertex_count.to_le_bytes());
+        }
+
+        // write copy
+        std::fs::write(out_dir.join(original.file_name().unwrap()), data)?;
+        Ok(reports)
+    }
+
+    /// write every model to `<out_dir>/<stem>/<stem>.obj`
+    pub fn write_to_obj(
+        &self,
+        file_path: &Path,
+        out_dir: &Path,
+        normals: NormalMode,
+        winding: Winding,
+    ) -> io::Result<()> {
         if self.0.is_empty() {
             return Ok(());
         }
         // create dir if it does not exist
         let stem = file_path.file_stem().unwrap().to_str().unwrap();
-        let dir_path = format!("out/{}", stem);
-        let dir_path = Path::new(&dir_path);
+        let dir_path = out_dir.join(stem);
         if !dir_path.exists() {
-            std::fs::create_dir_all(dir_path)?;
+            std::fs::create_dir_all(&dir_path)?;
         }
         // iterate through each model
-        let obj_file_path = format!("out/{}/{}.obj", stem, stem);
+        let obj_file_path = dir_path.join(format!("{}.obj", stem));
         let mut writer = File::create(obj_file_path)?;
         writeln!(writer, "mtllib {}.mtl", stem)?;
         let mut e_next = 0;
         for (i, model) in self.0.iter().enumerate() {
-            model.write_to_obj(&mut writer, &format!("{}-{}", stem, i), stem, &mut e_next)?;
+            model.write_to_obj(
+                &mut writer,
+                &format!("{}-{}", stem, i),
+                stem,
+                &mut e_next,
+                normals,
+                winding,
+            )?;
         }
 
         Ok(())
     }
+
+    /// write `<out_dir>/<stem>/<stem>.glb` with the textures embedded
+    pub fn write_to_gltf(
+        &self,
+        file_path: &Path,
+        out_dir: &Path,
+        textures: &Textures,
+        materials: &Materials,
+        normals: NormalMode,
+        winding: Winding,
+    ) -> Result<()> {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        // create dir if it does not exist
+        let stem = file_path.file_stem().unwrap().to_str().unwrap();
+        let dir_path = out_dir.join(stem);
+        if !dir_path.exists() {
+            std::fs::create_dir_all(&dir_path)?;
+        }
+        let mut writer = File::create(dir_path.join(format!("{}.glb", stem)))?;
+        gltf::write(
+            &mut writer,
+            &self.0,
+            textures,
+            materials,
+            normals,
+            winding,
+            stem,
+        )
+    }
+}
+
+/// which normals are exported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NormalMode {
+    /// as they are stored
+    #[default]
+    Stored,
+    /// unit length, normals with no length are recomputed
+    Renormalize,
+    /// smooth normals from the triangles
+    Recompute,
+}
+
+/// how the triangles of triangle strips are wound
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Winding {
+    /// each triangle faces the same way as the normals of its vertices,
+    /// if the normals do not say, it keeps the winding of the strip
+    #[default]
+    Normals,
+    /// every other triangle is reversed, starting with the second one
+    Parity,
+    /// every other triangle is reversed,
+    /// starting with the first triangle the normals say something about
+    SeededParity,
+}
+
+impl Winding {
+    // whether each triangle of a strip is wound as stored, from what its normals say
+    fn wind(&self, strip: &[(Option<bool>, bool)]) -> Vec<bool> {
+        // the first triangle as stored, like stripify writes them
+        let parity = |i: usize| i.is_multiple_of(2);
+        match self {
+            Winding::Normals => {
+                let mut last = None;
+                strip
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (stored, _))| {
+                        let as_stored =
+                            stored.unwrap_or_else(|| last.map_or(parity(i), |last: bool| !last));
+                        last = Some(as_stored);
+                        as_stored
+                    })
+                    .collect()
+            }
+            Winding::Parity => (0..strip.len()).map(parity).collect(),
+            Winding::SeededParity => {
+                let seed = strip
+                    .iter()
+                    .enumerate()
+                    .find_map(|(i, (stored, degenerate))| {
+                        stored.filter(|_| !degenerate).map(|stored| (i, stored))
+                    });
+                (0..strip.len())
+                    .map(|i| match seed {
+                        Some((seed, stored)) => stored ^ (i.abs_diff(seed) % 2 == 1),
+                        None => parity(i),
+                    })
+                    .collect()
+            }
+        }
+    }
 }
 
+/// how many triangles of a model are wound differently by each winding strategy
+/// than by the normals, and how many have no area and are skipped
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WindingReport {
+    /// triangles of the model, including the degenerate ones
+    pub triangles: usize,
+    /// triangles with no area
+    pub degenerate: usize,
+    /// triangles wound differently by Winding::Parity
+    pub parity: usize,
+    /// triangles wound differently by Winding::SeededParity
+    pub seeded_parity: usize,
+}
+
+/// how much of the space of the model it replaces a replacement uses
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpaceReport {
+    /// triangle strips of the replacement
+    pub triangle_strips: usize,
+    /// bytes, of the triangle strips of the replacement
+    pub len: usize,
+    /// bytes, of the triangle strips of the original
+    pub space: usize,
+}
+
+impl fmt::Display for SpaceReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} triangle strips, {:#x}/{:#x} bytes",
+            self.triangle_strips, self.len, self.space
+        )
+    }
+}
+
+/// a model is made of triangle strips
 pub struct Model {
+    /// in the order they are stored
     pub triangle_strips: Vec<TriangleStrip>,
+    /// bytes, where the signature of this model is
+    pub header: u64,
+    /// bytes, where the last triangle strip ends
+    pub end: u64,
 }
 
 impl Model {
-    pub fn read_from_bin<T: Read + Seek>(reader: &mut T) -> io::Result<Self> {
+    /// find and read the next model
+    pub fn read_from_bin<T: Read + Seek>(reader: &mut T) -> Result<Self> {
         // read model signature
         // println!("- Reading model signature");
         loop {
@@ -77,6 +331,7 @@ impl Model {
                 break;
             }
         }
+        let header = reader.stream_position()? - 4;
 
         // discard next 4 bytes (unknown)
         reader.seek(SeekFrom::Current(4))?;
@@ -91,13 +346,17 @@ impl Model {
             // example: 0x26D70 in DATCLARIS.BIN
 
             // read until 0x10 aligned and return an empty model
-            let reader_pos = reader.seek(SeekFrom::Current(0))?;
+            let reader_pos = reader.stream_position()?;
             let diff = 0x10 - (reader_pos % 0x10);
             if diff != 0x10 {
                 reader.seek(SeekFrom::Current(diff as _))?;
             }
 
-            return Ok(Self { triangle_strips });
+            return Ok(Self {
+                triangle_strips,
+                header,
+                end: header + 0x10,
+            });
         }
 
         let mut vertex_count = 0;
@@ -114,38 +373,102 @@ impl Model {
             vertex_count += triangle_strip.pos.len();
             triangle_strips.push(triangle_strip);
         }
+        let end = reader.stream_position()?;
 
-        Ok(Self { triangle_strips })
-    }
+        // read until 0x10 aligned
+        let reader_pos = reader.stream_position()?;
+        let diff = 0x10 - (reader_pos % 0x10);
+        if diff != 0x10 {
+            reader.seek(SeekFrom::Current(diff as _))?;
+        }
 
-    pub fn write_to_obj<T: Write>(
-        &self,
-        writer: &mut T,
-        model_name: &str,
-        material_prefix: &str,
-        e_next: &mut usize,
-    ) -> io::Result<()> {
-        // use obj_exporter::*;
+        Ok(Self {
+            triangle_strips,
+            header,
+            end,
+        })
+    }
 
-        // create elements for triangles
-        // this converts from triangle strips to triangles
-        // Map of materials to vector of triangles
-        let mut elements = HashMap::<u32, Vec<[usize; 3]>>::new();
+    /// the normal of each vertex of all triangle strips in order
+    pub fn normals(&self, mode: NormalMode) -> Vec<Vec3<f32>> {
+        // signed normalized, 127 is 1
+        let stored = self
+            .triangle_strips
+            .iter()
+            .flat_map(|ts| (0..ts.pos.len()).map(|i| ts.norm.get(i).copied()))
+            .map(|n| {
+                let n = n.unwrap_or(Vec3 { x: 0, y: 0, z: 0 });
+                Vec3 {
+                    x: (n.x as f32 / 127.).max(-1.),
+                    y: (n.y as f32 / 127.).max(-1.),
+                    z: (n.z as f32 / 127.).max(-1.),
+                }
+            })
+            .collect::<Vec<_>>();
+        match mode {
+            NormalMode::Stored => stored,
+            NormalMode::Renormalize => {
+                let smooth = self.smooth_normals(&stored);
+                stored
+                    .iter()
+                    .zip(smooth)
+                    .map(|(n, smooth)| n.normalize().unwrap_or(smooth))
+                    .collect()
+            }
+            NormalMode::Recompute => self.smooth_normals(&stored),
+        }
+    }
 
-        const CALC_WINDING_ORDER: bool = true;
+    // the area weighted mean of the triangles around each position, the triangles are wound
+    // using the stored normals. vertices at the same position are smoothed together,
+    // even across triangle strips
+    fn smooth_normals(&self, stored: &[Vec3<f32>]) -> Vec<Vec3<f32>> {
+        let pos = self
+            .triangle_strips
+            .iter()
+            .flat_map(|ts| ts.pos.iter())
+            .collect::<Vec<_>>();
+        let key = |p: &Vec3<f32>| (p.x.to_bits(), p.y.to_bits(), p.z.to_bits());
+        let mut sums = HashMap::<_, Vec3<f32>>::new();
+        for tri in self.triangles(stored, Winding::Normals).values().flatten() {
+            let [a, b, c] = tri.map(|e| *pos[e]);
+            let cross = (b - a).cross(&(c - a));
+            for e in tri {
+                let sum = sums.entry(key(pos[*e])).or_insert(Vec3 {
+                    x: 0.,
+                    y: 0.,
+                    z: 0.,
+                });
+                *sum = *sum + cross;
+            }
+        }
+        pos.iter()
+            .map(|p| {
+                sums.get(&key(p))
+                    .and_then(|sum| sum.normalize())
+                    .unwrap_or(Vec3 {
+                        x: 0.,
+                        y: 0.,
+                        z: 0.,
+                    })
+            })
+            .collect()
+    }
 
+    // for each triangle of each triangle strip, whether the normals of its vertices say it is
+    // wound as stored, none if they do not say, and whether it has no area
+    fn strip_windings(&self, normals: &[Vec3<f32>]) -> Vec<Vec<(Option<bool>, bool)>> {
+        let mut e_next = 0;
+        let mut windings = Vec::new();
         for ts in self.triangle_strips.iter() {
-            if CALC_WINDING_ORDER {
-                // for each (iterator, triangle) in the triangle strip
-                for (e_local, (a, b, c)) in ts
-                    .pos
-                    .iter()
-                    .zip(ts.norm.iter())
-                    .tuple_windows()
-                    .enumerate()
-                {
+            let strip = ts
+                .pos
+                .iter()
+                .zip(normals[e_next..e_next + ts.pos.len()].iter())
+                .tuple_windows()
+                .map(|(a, b, c)| {
                     // calculate the average normal (doesn't need to be normalised)
-                    let mean_norm = a.1.to() + b.1.to() + c.1.to();
+                    let mean_norm = *a.1 + *b.1 + *c.1;
 
                     // calculate the cross product of the vertex positions
                     let ab = b.0 - a.0;
@@ -154,64 +477,117 @@ impl Model {
 
                     // check if cross and average normal are in the same direction
                     let dot = cross.dot(&mean_norm);
+                    let stored = if dot != 0. { Some(dot > 0.) } else { None };
+                    (stored, cross.length() == 0.)
+                })
+                .collect();
+            windings.push(strip);
+            e_next += ts.pos.len();
+        }
+        windings
+    }
 
-                    // determine winding order based on sign of dot product
-                    let flip = dot > 0.;
-
-                    // add triangle to element buffer in correct order
-                    let triangles = elements.entry(ts.material).or_default();
-                    triangles.push(if flip {
-                        [
-                            *e_next + e_local,
-                            *e_next + e_local + 1,
-                            *e_next + e_local + 2,
-                        ]
-                    } else {
-                        [
-                            *e_next + e_local + 2,
-                            *e_next + e_local + 1,
-                            *e_next + e_local,
-                        ]
-                    });
+    /// triangles of each material, indexing the vertices of all triangle strips in order.
+    /// triangles with no area are skipped
+    pub fn triangles(
+        &self,
+        normals: &[Vec3<f32>],
+        winding: Winding,
+    ) -> HashMap<u32, Vec<[usize; 3]>> {
+        // create elements for triangles
+        // this converts from triangle strips to triangles
+        // Map of materials to vector of triangles
+        let mut elements = HashMap::<u32, Vec<[usize; 3]>>::new();
+        let mut e_next = 0;
+
+        let windings = self.strip_windings(normals);
+        for (ts, strip) in self.triangle_strips.iter().zip(windings) {
+            for (e_local, (as_stored, (_, degenerate))) in
+                winding.wind(&strip).into_iter().zip(strip).enumerate()
+            {
+                if degenerate {
+                    continue;
                 }
-            } else {
-                // for each triangle iterator in the triangle strip
-                for e_local in 0..ts.pos.len() - 2 {
-                    // do not flip flop winding order
-                    let triangles = elements.entry(ts.material).or_default();
-                    triangles.push(if e_local % 2 == 1 {
-                        [
-                            *e_next + e_local,
-                            *e_next + e_local + 1,
-                            *e_next + e_local + 2,
-                        ]
-                    } else {
-                        [
-                            *e_next + e_local + 2,
-                            *e_next + e_local + 1,
-                            *e_next + e_local,
-                        ]
-                    });
+                // add triangle to element buffer in correct order
+                let triangles = elements.entry(ts.material).or_default();
+                triangles.push(if as_stored {
+                    [e_next + e_local, e_next + e_local + 1, e_next + e_local + 2]
+                } else {
+                    [e_next + e_local + 2, e_next + e_local + 1, e_next + e_local]
+                });
+            }
+            e_next += ts.pos.len();
+        }
+
+        elements
+    }
+
+    /// how many triangles the winding strategies wind differently than the normals do
+    pub fn winding_report(&self, normals: &[Vec3<f32>]) -> WindingReport {
+        let mut report = WindingReport::default();
+        for strip in self.strip_windings(normals) {
+            let by_normals = Winding::Normals.wind(&strip);
+            let by_parity = Winding::Parity.wind(&strip);
+            let by_seeded_parity = Winding::SeededParity.wind(&strip);
+            for (i, (_, degenerate)) in strip.iter().enumerate() {
+                if *degenerate {
+                    report.degenerate += 1;
+                    continue;
                 }
+                report.triangles += 1;
+                report.parity += (by_normals[i] != by_parity[i]) as usize;
+                report.seeded_parity += (by_normals[i] != by_seeded_parity[i]) as usize;
             }
-            *e_next += ts.pos.len();
         }
+        report
+    }
+
+    /// write the model as an object of an obj file. its vertex indexes start after e_next,
+    /// which is moved past its vertices
+    pub fn write_to_obj<T: Write>(
+        &self,
+        writer: &mut T,
+        model_name: &str,
+        material_prefix: &str,
+        e_next: &mut usize,
+        normals: NormalMode,
+        winding: Winding,
+    ) -> io::Result<()> {
+        // use obj_exporter::*;
+
+        let normals = self.normals(normals);
+        let mut elements = self
+            .triangles(&normals, winding)
+            .into_iter()
+            .collect::<Vec<_>>();
+        elements.sort_unstable_by_key(|(material, _)| *material);
+        let mut normals = normals.iter();
 
         writeln!(writer, "o {}", model_name)?;
 
         for ts in self.triangle_strips.iter() {
-            for pos in &ts.pos {
-                writeln!(writer, "v {} {} {}", pos.x, pos.y, pos.z)?;
-            }
-
-            for norm in &ts.norm {
-                writeln!(
+            // vertex colors after the position, 1 is full intensity.
+            // alpha is only written when it is not the usual 1, few programs read it
+            for (i, pos) in ts.pos.iter().enumerate() {
+                let color = ts.color.get(i).unwrap_or(&NEUTRAL_COLOR);
+                write!(
                     writer,
-                    "vn {} {} {}",
-                    norm.x as f32 / 255.,
-                    norm.y as f32 / 255.,
-                    norm.z as f32 / 255.
+                    "v {} {} {} {} {} {}",
+                    pos.x,
+                    pos.y,
+                    pos.z,
+                    color.r as f32 / 128.,
+                    color.g as f32 / 128.,
+                    color.b as f32 / 128.
                 )?;
+                if color.a != NEUTRAL_COLOR.a {
+                    write!(writer, " {}", color.a as f32 / 128.)?;
+                }
+                writeln!(writer)?;
+            }
+
+            for norm in normals.by_ref().take(ts.pos.len()) {
+                writeln!(writer, "vn {} {} {}", norm.x, norm.y, norm.z)?;
             }
 
             for uv in &ts.uv {
@@ -221,6 +597,7 @@ impl Model {
         for (material, triangles) in elements.iter() {
             writeln!(writer, "usemtl {}-{}", material_prefix, material)?;
             for tri in triangles {
+                let tri = tri.map(|e| *e_next + e);
                 writeln!(
                     writer,
                     "f {}/{}/{} {}/{}/{} {}/{}/{}",
@@ -236,89 +613,169 @@ impl Model {
                 )?;
             }
         }
+        *e_next += self
+            .triangle_strips
+            .iter()
+            .map(|ts| ts.pos.len())
+            .sum::<usize>();
 
         Ok(())
     }
 
-    pub fn write_to_bin<T: Write + Seek>(&self, writer: &mut T) -> io::Result<()> {
-        //! THIS WAS A TEST!
-        //! THIS DOES NOT WORK
+    /// write the triangle strips, with a material record before the first one and each time
+    /// the material changes. the material records are copied from records, by material.
+    /// the vertex count in the model header is not written
+    pub fn write_to_bin<T: Write + Seek>(
+        &self,
+        writer: &mut T,
+        records: &HashMap<u32, [u8; MATERIAL_RECORD_LEN]>,
+    ) -> Result<()> {
+        let mut material = None;
+        for (i, strip) in self.triangle_strips.iter().enumerate() {
+            if material != Some(strip.material) {
+                material = Some(strip.material);
+                let record = records
+                    .get(&strip.material)
+                    .ok_or(Error::NoMaterialRecord {
+                        index: None,
+                        material: strip.material,
+                    })?;
+                writer.write_all(record)?;
+                Self::write_end(writer)?;
+            }
 
-        // write model signature
-        writer.write_u32::<LE>(0x00001000)?;
+            // write triangle strip
+            strip.write(writer)?;
+
+            // the end of the last triangle strip is after the model
+            if i != self.triangle_strips.len() - 1 {
+                // write STCYCL
+                writer.write_u32::<LE>(0x01000404)?;
+                Self::write_end(writer)?;
+            }
+        }
 
-        // write unknown bytes
-        writer.write_u32::<LE>(0)?;
+        Ok(())
+    }
 
-        // write expected vertex count
-        let mut expected_vertex_count = 0;
-        for ts in self.triangle_strips.iter() {
-            expected_vertex_count += ts.pos.len();
+    // padding until 0x10 aligned, then MSCAL to draw what was uploaded
+    fn write_end<T: Write + Seek>(writer: &mut T) -> io::Result<()> {
+        while writer.stream_position()? % 0x10 != 0 {
+            writer.write_u32::<LE>(0x00000000)?;
         }
-        writer.write_u32::<LE>(expected_vertex_count as u32)?;
-
-        // padding?
-        writer.write_u32::<LE>(0x00000000)?;
-
-        // write triangle strips
-        for strip in &self.triangle_strips {
-            // write triangle strip signature
-            let index_count = 0x03; // should be 3 please keep it simple
-            let signature = [
-                0x00,
-                0x80,
-                0x04,
-                0x60,
-                index_count,
-                0x00,
-                0x00,
-                0x00,
-                0x41,
-                0x00,
-                0x00,
-                0x00,
-                0x00,
-                0x40,
-                0x1E,
-                0x30,
-                0x00,
-                0xC0,
-                0x1E,
-                0x30,
-                0x05,
-                0x01,
-                0x00,
-                0x01,
-                0x00,
-                0x00,
-                0x00,
-                0x20,
-                0x40,
-                0x40,
-                0x40,
-                0x40,
-            ];
-            writer.write(&signature)?;
+        let end = [
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00,
+            0x00, 0x14,
+        ];
+        writer.write_all(&end)
+    }
+}
 
-            // write triangle strip
-            strip.write(writer)?;
+/// bytes of a material record. material records are an UNPACK of 4 quadwords,
+/// the material is in the first one after FE FF FF FF
+pub const MATERIAL_RECORD_LEN: usize = 0x44;
+const MATERIAL_RECORD_HEADER: [u8; 4] = [0x00, 0x80, 0x04, 0x6C];
 
-            // write end 1
-            writer.write_u32::<LE>(0x01000404)?;
+fn is_material_record(data: &[u8], row: usize) -> bool {
+    const FIND_MAT_SIGNATURE: [u8; 4] = [0xFE, 0xFF, 0xFF, 0xFF];
+    data.get(row..row + 4) == Some(&MATERIAL_RECORD_HEADER)
+        && data.get(row + 0xC..row + 0x10) == Some(&FIND_MAT_SIGNATURE)
+}
 
-            // write padding until 0x10 aligned
-            while writer.seek(SeekFrom::Current(0))? % 0x10 != 0 {
-                writer.write_u32::<LE>(0x00000000)?;
-            }
+/// bytes, where each material record of a model is
+pub(crate) fn material_record_offsets<'a>(
+    data: &'a [u8],
+    model: &Model,
+) -> impl Iterator<Item = usize> + 'a {
+    (model.header as usize + 0x10..model.end as usize)
+        .step_by(0x10)
+        .filter(|row| is_material_record(data, *row) && row + MATERIAL_RECORD_LEN <= data.len())
+}
+
+/// every material record of a model, with the material it sets
+pub(crate) fn material_records(
+    data: &[u8],
+    model: &Model,
+) -> Vec<(u32, [u8; MATERIAL_RECORD_LEN])> {
+    material_record_offsets(data, model)
+        .map(|row| &data[row..row + MATERIAL_RECORD_LEN])
+        .map(|record| {
+            let material = u32::from_le_bytes(record[0x10..0x14].try_into().unwrap()) / 2;
+            (material, record.try_into().unwrap())
+        })
+        .collect()
+}
 
-            // write end 2
-            let end = [
-                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04,
-                0x00, 0x00, 0x14,
-            ];
-            writer.write(&end)?;
+// where the first material record or triangle strip packet of a model is
+fn find_packets(data: &[u8], model: &Model) -> usize {
+    const FIND_TS_SIGNATURE: [u8; 8] = [0x00, 0x00, 0x00, 0x20, 0x40, 0x40, 0x40, 0x40];
+    (model.header as usize + 0x10..model.end as usize)
+        .step_by(0x10)
+        .find(|row| {
+            // the strip header starts a row before its STMASK
+            is_material_record(data, *row)
+                || data.get(row + 0x18..row + 0x20) == Some(&FIND_TS_SIGNATURE)
+        })
+        .unwrap_or(model.header as usize + 0x10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::Vec2;
+
+    fn strip(material: u32) -> TriangleStrip {
+        TriangleStrip {
+            pos: [0., 1., 2.].map(|x| Vec3 { x, y: 0., z: 0. }).to_vec(),
+            norm: vec![Vec3 { x: 0, y: 0, z: 127 }; 3],
+            uv: vec![Vec2 { x: 0., y: 0. }; 3],
+            color: vec![NEUTRAL_COLOR; 3],
+            material,
+            offset: 0,
         }
+    }
 
-        Ok(())
+    // a material record whose TEX0 is different for each material
+    fn record(material: u32) -> [u8; MATERIAL_RECORD_LEN] {
+        let mut record = [0; MATERIAL_RECORD_LEN];
+        record[..4].copy_from_slice(&MATERIAL_RECORD_HEADER);
+        record[0xC..0x10].copy_from_slice(&[0xFE, 0xFF, 0xFF, 0xFF]);
+        record[0x10..0x14].copy_from_slice(&(material * 2).to_le_bytes());
+        record[0x20..0x44].fill(material as u8);
+        record
+    }
+
+    #[test]
+    fn writes_the_record_of_each_material() {
+        let model = Model {
+            triangle_strips: vec![strip(1), strip(1), strip(2)],
+            header: 0,
+            end: 0,
+        };
+        let records = HashMap::from([(1, record(1)), (2, record(2))]);
+        let mut writer = Cursor::new(Vec::new());
+        model.write_to_bin(&mut writer, &records).unwrap();
+        let data = writer.into_inner();
+
+        let model = Model {
+            header: 0,
+            end: data.len() as u64 + 0x10,
+            ..model
+        };
+        let written = material_records(&[&[0; 0x10], &data[..]].concat(), &model);
+        assert_eq!(written, [(1, record(1)), (2, record(2))]);
+    }
+
+    #[test]
+    fn rejects_materials_without_a_record() {
+        let model = Model {
+            triangle_strips: vec![strip(1), strip(3)],
+            header: 0,
+            end: 0,
+        };
+        let records = HashMap::from([(1, record(1))]);
+        let mut writer = Cursor::new(Vec::new());
+        let e = model.write_to_bin(&mut writer, &records).unwrap_err();
+        assert!(matches!(e, Error::NoMaterialRecord { material: 3, .. }));
     }
 }
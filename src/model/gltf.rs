@@ -0,0 +1,257 @@
+use super::{
+    material::{AlphaMode, Material, Materials},
+    Model, NormalMode, Winding,
+};
+use crate::{error::Result, texture::Textures};
+use byteorder::{WriteBytesExt, LE};
+use serde_json::{json, Value};
+use std::io::Write;
+
+// glTF 2.0 binary (.glb): a json chunk describing the scene, followed by a
+// binary chunk with all of the vertices, indices and png images
+
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+#[derive(Default)]
+struct Buffer {
+    data: Vec<u8>,
+    views: Vec<Value>,
+    accessors: Vec<Value>,
+}
+
+impl Buffer {
+    fn push_view(&mut self, bytes: &[u8], target: Option<u32>) -> usize {
+        // accessors need their data to be 4 byte aligned
+        while !self.data.len().is_multiple_of(4) {
+            self.data.push(0);
+        }
+        let mut view = json!({
+            "buffer": 0,
+            "byteOffset": self.data.len(),
+            "byteLength": bytes.len(),
+        });
+        if let Some(target) = target {
+            view["target"] = json!(target);
+        }
+        self.data.extend_from_slice(bytes);
+        self.views.push(view);
+        self.views.len() - 1
+    }
+
+    fn push_floats<const N: usize>(&mut self, values: &[[f32; N]], bounds: bool) -> usize {
+        let bytes = values
+            .iter()
+            .flatten()
+            .flat_map(|f| f.to_le_bytes())
+            .collect::<Vec<_>>();
+        let view = self.push_view(&bytes, Some(ARRAY_BUFFER));
+        let mut accessor = json!({
+            "bufferView": view,
+            "componentType": FLOAT,
+            "count": values.len(),
+            "type": match N {
+                2 => "VEC2",
+                3 => "VEC3",
+                4 => "VEC4",
+                _ => unreachable!(),
+            },
+        });
+        // positions need their bounds
+        if bounds {
+            let mut min = [f32::MAX; N];
+            let mut max = [f32::MIN; N];
+            for value in values {
+                for i in 0..N {
+                    min[i] = min[i].min(value[i]);
+                    max[i] = max[i].max(value[i]);
+                }
+            }
+            accessor["min"] = json!(min.to_vec());
+            accessor["max"] = json!(max.to_vec());
+        }
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    fn push_indices(&mut self, triangles: &[[usize; 3]]) -> usize {
+        let bytes = triangles
+            .iter()
+            .flatten()
+            .flat_map(|i| (*i as u32).to_le_bytes())
+            .collect::<Vec<_>>();
+        let view = self.push_view(&bytes, Some(ELEMENT_ARRAY_BUFFER));
+        self.accessors.push(json!({
+            "bufferView": view,
+            "componentType": UNSIGNED_INT,
+            "count": triangles.len() * 3,
+            "type": "SCALAR",
+        }));
+        self.accessors.len() - 1
+    }
+}
+
+/// each model is a mesh, and each material used by its triangle strips is a primitive
+pub fn write<T: Write>(
+    writer: &mut T,
+    models: &[Model],
+    textures: &Textures,
+    materials: &Materials,
+    normals: NormalMode,
+    winding: Winding,
+    name: &str,
+) -> Result<()> {
+    let mut buffer = Buffer::default();
+
+    // embed every texture, in order
+    let mut texture_indexes = textures.0.keys().copied().collect::<Vec<_>>();
+    texture_indexes.sort_unstable();
+    let mut images = Vec::new();
+    for id in texture_indexes.iter() {
+        let view = buffer.push_view(&textures.0[id].to_png()?, None);
+        images.push(json!({
+            "name": format!("{}-{}", name, id),
+            "bufferView": view,
+            "mimeType": "image/png",
+        }));
+    }
+    let gltf_textures = (0..images.len())
+        .map(|i| json!({ "sampler": 0, "source": i }))
+        .collect::<Vec<_>>();
+
+    // materials are made when a triangle strip first uses them
+    let mut gltf_materials = Vec::<Value>::new();
+    let mut material_indexes = Vec::new();
+
+    let mut meshes = Vec::new();
+    for (i, model) in models.iter().enumerate() {
+        let pos = model
+            .triangle_strips
+            .iter()
+            .flat_map(|ts| ts.pos.iter().map(|p| [p.x, p.y, p.z]))
+            .collect::<Vec<_>>();
+        // glTF requires normals to be unit length
+        let model_normals = model.normals(normals);
+        let norm = model_normals
+            .iter()
+            .map(|n| n.normalize().map_or([0., 1., 0.], |n| [n.x, n.y, n.z]))
+            .collect::<Vec<_>>();
+        let uv = model
+            .triangle_strips
+            .iter()
+            .flat_map(|ts| ts.uv.iter().map(|uv| [uv.x, uv.y]))
+            .collect::<Vec<_>>();
+        // glTF colors are multiplied with the texture, so they can not be brighter than it
+        let color = model
+            .triangle_strips
+            .iter()
+            .flat_map(|ts| {
+                ts.color
+                    .iter()
+                    .map(|c| [c.r, c.g, c.b, c.a].map(|c| (c as f32 / 128.).min(1.)))
+            })
+            .collect::<Vec<_>>();
+
+        // all primitives of a mesh share its vertices
+        let mut attributes = json!({ "POSITION": buffer.push_floats(&pos, true) });
+        if norm.len() == pos.len() {
+            attributes["NORMAL"] = json!(buffer.push_floats(&norm, false));
+        }
+        if uv.len() == pos.len() {
+            attributes["TEXCOORD_0"] = json!(buffer.push_floats(&uv, false));
+        }
+        if color.len() == pos.len() {
+            attributes["COLOR_0"] = json!(buffer.push_floats(&color, false));
+        }
+
+        let mut elements = model
+            .triangles(&model_normals, winding)
+            .into_iter()
+            .collect::<Vec<_>>();
+        elements.sort_unstable_by_key(|(material, _)| *material);
+        let mut primitives = Vec::new();
+        for (material, triangles) in elements {
+            let material_index = match material_indexes.iter().position(|m| *m == material) {
+                Some(index) => index,
+                None => {
+                    let mut gltf_material = json!({
+                        "name": format!("{}-{}", name, material),
+                        "pbrMetallicRoughness": { "metallicFactor": 0.0 },
+                    });
+                    if let Some(Material {
+                        texture: Some(texture),
+                        alpha,
+                        ..
+                    }) = materials.0.get(&material)
+                    {
+                        let index = texture_indexes.iter().position(|id| id == texture).unwrap();
+                        gltf_material["pbrMetallicRoughness"]["baseColorTexture"] =
+                            json!({ "index": index });
+                        if *alpha != AlphaMode::Opaque {
+                            gltf_material["alphaMode"] = json!(alpha.name());
+                        }
+                    }
+                    gltf_materials.push(gltf_material);
+                    material_indexes.push(material);
+                    gltf_materials.len() - 1
+                }
+            };
+            primitives.push(json!({
+                "attributes": attributes,
+                "indices": buffer.push_indices(&triangles),
+                "material": material_index,
+            }));
+        }
+
+        meshes.push(json!({
+            "name": format!("{}-{}", name, i),
+            "primitives": primitives,
+        }));
+    }
+
+    let nodes = (0..meshes.len())
+        .map(|i| json!({ "name": meshes[i]["name"], "mesh": i }))
+        .collect::<Vec<_>>();
+    let mut gltf = json!({
+        "asset": { "version": "2.0", "generator": "nights_into_bins" },
+        "scene": 0,
+        "scenes": [{ "name": name, "nodes": (0..nodes.len()).collect::<Vec<_>>() }],
+        "nodes": nodes,
+        "meshes": meshes,
+        "materials": gltf_materials,
+        "buffers": [{ "byteLength": buffer.data.len() }],
+        "bufferViews": buffer.views,
+        "accessors": buffer.accessors,
+    });
+    if !images.is_empty() {
+        gltf["images"] = json!(images);
+        gltf["textures"] = json!(gltf_textures);
+        gltf["samplers"] = json!([{}]);
+    }
+
+    // chunks are padded to 4 bytes, json with spaces
+    let mut json = serde_json::to_vec(&gltf).map_err(std::io::Error::from)?;
+    while !json.len().is_multiple_of(4) {
+        json.push(b' ');
+    }
+    let mut bin = buffer.data;
+    while !bin.len().is_multiple_of(4) {
+        bin.push(0);
+    }
+
+    // header
+    writer.write_all(b"glTF")?;
+    writer.write_u32::<LE>(2)?;
+    writer.write_u32::<LE>((12 + 8 + json.len() + 8 + bin.len()) as u32)?;
+    // json chunk
+    writer.write_u32::<LE>(json.len() as u32)?;
+    writer.write_all(b"JSON")?;
+    writer.write_all(&json)?;
+    // binary chunk
+    writer.write_u32::<LE>(bin.len() as u32)?;
+    writer.write_all(b"BIN\0")?;
+    writer.write_all(&bin)?;
+    Ok(())
+}
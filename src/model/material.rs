@@ -0,0 +1,225 @@
+use crate::{
+    error::Result,
+    model::{material_record_offsets, material_records, Models, MATERIAL_RECORD_LEN},
+    texture::{
+        texture_format::TextureFormat, texture_header::conversion, Texture, TextureId, Textures,
+    },
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    io::Write,
+    path::Path,
+};
+
+/// how the alpha of a texture is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlphaMode {
+    /// every pixel is opaque
+    Opaque,
+    /// every pixel is opaque or transparent, like with 16-bit palettes
+    Mask,
+    /// some pixels are partly transparent
+    Blend,
+}
+
+impl AlphaMode {
+    /// how the alpha of texture has to be drawn
+    pub fn of(texture: &Texture) -> Self {
+        if texture.0.iter().all(|c| c.a == 0xFF) {
+            AlphaMode::Opaque
+        } else if texture.0.iter().all(|c| c.a == 0xFF || c.a == 0x00) {
+            AlphaMode::Mask
+        } else {
+            AlphaMode::Blend
+        }
+    }
+
+    /// the glTF alpha mode
+    pub fn name(&self) -> &'static str {
+        match self {
+            AlphaMode::Opaque => "OPAQUE",
+            AlphaMode::Mask => "MASK",
+            AlphaMode::Blend => "BLEND",
+        }
+    }
+}
+
+/// a material, and the texture it is drawn with
+pub struct Material {
+    /// the texture the TEX0 of its material records points at,
+    /// or the texture of the first bank with the same index as the material
+    pub texture: Option<TextureId>,
+    /// whether the texture was found from the material records, not the index
+    pub matched: bool,
+    /// how the alpha of the texture is drawn
+    pub alpha: AlphaMode,
+    /// the models with triangle strips that use it
+    pub models: Vec<usize>,
+}
+
+/// materials of a binary file, by the material in the material records of triangle strips.
+/// every texture of the first bank is also a material of its index, unless a model uses it
+pub struct Materials(pub BTreeMap<u32, Material>);
+
+impl Materials {
+    /// resolve the material records of the models in data to textures
+    pub fn new(data: &[u8], models: &Models, textures: &Textures) -> Self {
+        let mut materials = BTreeMap::new();
+        for (id, texture) in textures.0.iter().filter(|(id, _)| id.bank == 0) {
+            materials.insert(
+                id.index as u32,
+                Material {
+                    texture: Some(*id),
+                    matched: false,
+                    alpha: AlphaMode::of(texture),
+                    models: Vec::new(),
+                },
+            );
+        }
+
+        // the textures each material record draws with
+        let mut matches = BTreeMap::<u32, BTreeSet<TextureId>>::new();
+        for (i, model) in models.0.iter().enumerate() {
+            for (material, record) in material_records(data, model) {
+                let ids = textures.1.iter().enumerate().flat_map(|(bank, tfs)| {
+                    tfs.0.iter().enumerate().filter_map(move |(index, tf)| {
+                        tex0s(&record)
+                            .any(|tex0| tex0_matches(tex0, tf))
+                            .then_some(TextureId { bank, index })
+                    })
+                });
+                matches.entry(material).or_default().extend(ids);
+            }
+            for ts in model.triangle_strips.iter() {
+                let material = materials.entry(ts.material).or_insert(Material {
+                    texture: None,
+                    matched: false,
+                    alpha: AlphaMode::Opaque,
+                    models: Vec::new(),
+                });
+                if material.models.last() != Some(&i) {
+                    material.models.push(i);
+                }
+            }
+        }
+
+        // the texture of the same index wins if more than one matches
+        for (id, material) in materials.iter_mut() {
+            let Some(ids) = matches.get(id) else {
+                continue;
+            };
+            let index = TextureId::first_bank(*id as usize);
+            let texture = match ids.contains(&index) {
+                true => Some(index),
+                false => ids.first().copied(),
+            };
+            if let Some(texture) = texture {
+                material.texture = Some(texture);
+                material.matched = true;
+                material.alpha = AlphaMode::of(&textures.0[&texture]);
+            }
+        }
+        Self(materials)
+    }
+
+    /// materials that are used by models, but have no texture
+    pub fn unmapped(&self) -> impl Iterator<Item = (&u32, &Material)> {
+        self.0.iter().filter(|(_, m)| m.texture.is_none())
+    }
+
+    /// materials that are used by models, and drawn with the texture of the same index
+    /// because none of their material records point at a texture
+    pub fn unmatched(&self) -> impl Iterator<Item = (&u32, &Material)> {
+        self.0
+            .iter()
+            .filter(|(_, m)| m.texture.is_some() && !m.matched && !m.models.is_empty())
+    }
+
+    /// write `<out_dir>/<stem>/<stem>.mtl` with every material
+    pub fn write_to_mtl(&self, file_path: &Path, out_dir: &Path) -> Result<()> {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+
+        let stem = file_path.file_stem().unwrap().to_str().unwrap();
+        let mtl_filename = out_dir.join(stem).join(format!("{}.mtl", stem));
+
+        // write mtl file
+        let mut mtl = File::create(mtl_filename)?;
+
+        for (id, material) in self.0.iter() {
+            writeln!(mtl, "newmtl {}-{}", &stem, id)?;
+            let Some(texture) = material.texture else {
+                writeln!(mtl, "# no texture")?;
+                continue;
+            };
+            writeln!(mtl, "map_Kd {}-{}.png", &stem, texture)?;
+            // the alpha of the png
+            if material.alpha != AlphaMode::Opaque {
+                writeln!(mtl, "map_d {}-{}.png", &stem, texture)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// bytes, where each TEX0 in the material records of models is that draws with the texture
+/// and palette of a texture format
+pub(crate) fn tex0_offsets(data: &[u8], models: &Models, tf: &TextureFormat) -> Vec<usize> {
+    let mut offsets = Vec::new();
+    for model in models.0.iter() {
+        for row in material_record_offsets(data, model) {
+            let record = &data[row..row + MATERIAL_RECORD_LEN];
+            for (i, tex0) in tex0s(record).enumerate() {
+                if tex0_matches(tex0, tf) {
+                    offsets.push(row + 4 + i * 8);
+                }
+            }
+        }
+    }
+    offsets
+}
+
+/// make the TEX0 at each offset draw the texture with the size of new_tf: TW and TH,
+/// and TBW if the buffer width changed
+pub(crate) fn resize_tex0s(data: &mut [u8], offsets: &[usize], new_tf: &TextureFormat) {
+    let upload = new_tf.texture_upload();
+    let conversion = conversion(new_tf.psm(), new_tf.double_size);
+    for offset in offsets {
+        let dword = &mut data[*offset..*offset + 8];
+        let mut tex0 = u64::from_le_bytes((&*dword).try_into().unwrap());
+        let mut set = |start: u32, len: u32, value: u64| {
+            let mask = ((1 << len) - 1) << start;
+            tex0 = (tex0 & !mask) | ((value << start) & mask);
+        };
+        if upload.dbw != new_tf.gs.texture.dbw {
+            set(14, 6, upload.dbw as u64 * conversion.x as u64);
+        }
+        set(26, 4, new_tf.size.x.trailing_zeros() as _);
+        set(30, 4, new_tf.size.y.trailing_zeros() as _);
+        dword.copy_from_slice(&tex0.to_le_bytes());
+    }
+}
+
+// the doublewords of the GS data of a material record, any of them can be a TEX0
+fn tex0s(record: &[u8]) -> impl Iterator<Item = u64> + '_ {
+    record[4..]
+        .chunks_exact(8)
+        .map(|dword| u64::from_le_bytes(dword.try_into().unwrap()))
+}
+
+// whether TEX0 draws with the texture and palette of a texture format:
+// TBP0 and CBP are where they were uploaded, PSM, TW and TH how they are drawn
+fn tex0_matches(tex0: u64, tf: &TextureFormat) -> bool {
+    let bits = |start: u32, len: u32| (tex0 >> start) & ((1 << len) - 1);
+    let (Some(psm), Some(tw_th)) = (tf.gs.psm(), tf.gs.tw_th()) else {
+        return false;
+    };
+    bits(0, 14) == tf.gs.texture.dbp as u64
+        && bits(20, 6) == psm as u64
+        && bits(26, 4) == tw_th.x as u64
+        && bits(30, 4) == tw_th.y as u64
+        && bits(37, 14) == tf.gs.palette.dbp as u64
+}
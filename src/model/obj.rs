@@ -0,0 +1,280 @@
+use super::{
+    triangle_strip::{self, TriangleStrip, NEUTRAL_COLOR},
+    Model,
+};
+use crate::{
+    texture::Color,
+    vec::{Vec2, Vec3},
+};
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+// a vertex is a position, and optionally a texture coordinate and a normal
+type Vertex = (usize, Option<usize>, Option<usize>);
+
+fn invalid(line: usize, message: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("line {}: {}", line + 1, message),
+    )
+}
+
+// the number after the last '-', e.g. 3 in DATCLARIS-3
+fn index(name: &str) -> Option<usize> {
+    name.rsplit_once('-')?.1.parse().ok()
+}
+
+/// read the objects named `<stem>-<i>` in an obj file, as replacements for model i.
+/// materials are named `<stem>-<material>`, like the exported mtl file.
+/// vertex colors after the position are optional, and so is alpha after them
+pub fn read(path: &Path) -> io::Result<HashMap<usize, Model>> {
+    let obj = std::fs::read_to_string(path)?;
+
+    let mut pos = Vec::new();
+    let mut color = Vec::new();
+    let mut uv = Vec::new();
+    let mut norm = Vec::new();
+    // triangles of each material of each object
+    let mut objects = HashMap::<usize, HashMap<u32, Vec<[Vertex; 3]>>>::new();
+    let mut object = None;
+    let mut material = 0;
+
+    for (line_index, line) in obj.lines().enumerate() {
+        let mut words = line.split_whitespace();
+        let Some(keyword) = words.next() else {
+            continue;
+        };
+        let floats = || -> io::Result<Vec<f32>> {
+            words
+                .clone()
+                .map(|word| {
+                    word.parse()
+                        .map_err(|_| invalid(line_index, "invalid number"))
+                })
+                .collect()
+        };
+        match keyword {
+            "v" => {
+                let v = floats()?;
+                if v.len() < 3 {
+                    return Err(invalid(line_index, "position needs 3 numbers"));
+                }
+                pos.push(Vec3 {
+                    x: v[0],
+                    y: v[1],
+                    z: v[2],
+                });
+                // optional vertex colors and alpha, 1 is full intensity
+                let to_u8 = |c: f32| (c * 128.).round().clamp(0., 255.) as u8;
+                color.push(if v.len() >= 6 {
+                    Color {
+                        r: to_u8(v[3]),
+                        g: to_u8(v[4]),
+                        b: to_u8(v[5]),
+                        a: v.get(6).map_or(NEUTRAL_COLOR.a, |a| to_u8(*a)),
+                    }
+                } else {
+                    NEUTRAL_COLOR
+                });
+            }
+            "vt" => {
+                let v = floats()?;
+                if v.len() < 2 {
+                    return Err(invalid(line_index, "texture coordinate needs 2 numbers"));
+                }
+                uv.push(Vec2 { x: v[0], y: v[1] });
+            }
+            "vn" => {
+                let v = floats()?;
+                if v.len() < 3 {
+                    return Err(invalid(line_index, "normal needs 3 numbers"));
+                }
+                // the game stores normals as bytes
+                let len = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+                let to_i8 = |c: f32| {
+                    if len == 0. {
+                        0
+                    } else {
+                        (c / len * 127.).round() as i8
+                    }
+                };
+                norm.push(Vec3 {
+                    x: to_i8(v[0]),
+                    y: to_i8(v[1]),
+                    z: to_i8(v[2]),
+                });
+            }
+            "o" | "g" => {
+                object = words.next().and_then(index);
+            }
+            "usemtl" => {
+                material = words
+                    .next()
+                    .and_then(index)
+                    .ok_or_else(|| invalid(line_index, "material should be named <stem>-<i>"))?
+                    as u32;
+            }
+            "f" => {
+                let Some(object) = object else {
+                    return Err(invalid(
+                        line_index,
+                        "face is not in an object named <stem>-<i>",
+                    ));
+                };
+                // indexes start at 1, negative indexes count back from the end
+                let resolve = |i: &str, len: usize| -> io::Result<usize> {
+                    let i = i
+                        .parse::<isize>()
+                        .map_err(|_| invalid(line_index, "invalid index"))?;
+                    let i = if i < 0 { len as isize + i } else { i - 1 };
+                    if i < 0 || i as usize >= len {
+                        return Err(invalid(line_index, "index out of range"));
+                    }
+                    Ok(i as usize)
+                };
+                let mut vertexes = Vec::new();
+                for word in words {
+                    let mut indexes = word.split('/');
+                    let p = resolve(indexes.next().unwrap(), pos.len())?;
+                    let t = match indexes.next() {
+                        Some(t) if !t.is_empty() => Some(resolve(t, uv.len())?),
+                        _ => None,
+                    };
+                    let n = match indexes.next() {
+                        Some(n) if !n.is_empty() => Some(resolve(n, norm.len())?),
+                        _ => None,
+                    };
+                    vertexes.push((p, t, n));
+                }
+                if vertexes.len() < 3 {
+                    return Err(invalid(line_index, "face needs 3 vertexes"));
+                }
+                // polygons become fans of triangles
+                let triangles = objects
+                    .entry(object)
+                    .or_default()
+                    .entry(material)
+                    .or_default();
+                for i in 1..vertexes.len() - 1 {
+                    triangles.push([vertexes[0], vertexes[i], vertexes[i + 1]]);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let mut models = HashMap::new();
+    for (object, materials) in objects {
+        let mut materials = materials.into_iter().collect::<Vec<_>>();
+        materials.sort_unstable_by_key(|(material, _)| *material);
+
+        let mut triangle_strips = Vec::new();
+        for (material, triangles) in materials {
+            // number each unique vertex
+            let mut vertexes = Vec::new();
+            let mut vertex_indexes = HashMap::new();
+            let triangles = triangles
+                .iter()
+                .map(|tri| {
+                    tri.map(|v| {
+                        *vertex_indexes.entry(v).or_insert_with(|| {
+                            vertexes.push(v);
+                            vertexes.len() - 1
+                        })
+                    })
+                })
+                // skip triangles that do not have 3 different vertexes
+                .filter(|tri| tri[0] != tri[1] && tri[1] != tri[2] && tri[2] != tri[0])
+                .collect::<Vec<_>>();
+
+            for strip in TriangleStrip::stripify(&triangles, triangle_strip::MAX_VERTICES) {
+                let strip = strip.iter().map(|i| vertexes[*i]);
+                triangle_strips.push(TriangleStrip {
+                    pos: strip.clone().map(|(p, _, _)| pos[p]).collect(),
+                    color: strip.clone().map(|(p, _, _)| color[p]).collect(),
+                    norm: strip
+                        .clone()
+                        .map(|(_, _, n)| n.map_or(Vec3 { x: 0, y: 0, z: 0 }, |n| norm[n]))
+                        .collect(),
+                    uv: strip
+                        .map(|(_, t, _)| t.map_or(Vec2 { x: 0., y: 0. }, |t| uv[t]))
+                        .collect(),
+                    material,
+                    offset: 0,
+                });
+            }
+        }
+
+        models.insert(
+            object,
+            Model {
+                triangle_strips,
+                header: 0,
+                end: 0,
+            },
+        );
+    }
+    Ok(models)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // read an obj file written to the temporary directory
+    fn read_str(name: &str, obj: &str) -> io::Result<HashMap<usize, Model>> {
+        let path = std::env::temp_dir().join(format!("nights_into_bins-{}.obj", name));
+        std::fs::write(&path, obj)?;
+        let models = read(&path);
+        std::fs::remove_file(&path)?;
+        models
+    }
+
+    // the triangles of each strip, as the x of their positions,
+    // each rotated so its smallest vertex is first
+    fn triangles(model: &Model) -> Vec<[usize; 3]> {
+        let mut triangles = Vec::new();
+        for strip in model.triangle_strips.iter() {
+            let xs = strip.pos.iter().map(|p| p.x as usize).collect::<Vec<_>>();
+            for (i, w) in xs.windows(3).enumerate() {
+                let tri = if i % 2 == 0 {
+                    [w[0], w[1], w[2]]
+                } else {
+                    [w[1], w[0], w[2]]
+                };
+                let j = (0..3).min_by_key(|j| tri[*j]).unwrap();
+                triangles.push([tri[j], tri[(j + 1) % 3], tri[(j + 2) % 3]]);
+            }
+        }
+        triangles.sort_unstable();
+        triangles
+    }
+
+    const POSITIONS: &str = "v 0 0 0\nv 1 0 0\nv 2 0 0\nv 3 0 0\nv 4 0 0\n";
+
+    #[test]
+    fn reads_negative_indexes() {
+        let obj = format!("{}o TEST-0\nusemtl TEST-1\nf -5 -4 -3\n", POSITIONS);
+        let models = read_str("negative", &obj).unwrap();
+        assert_eq!(triangles(&models[&0]), [[0, 1, 2]]);
+        assert_eq!(models[&0].triangle_strips[0].material, 1);
+    }
+
+    #[test]
+    fn reads_polygons_as_fans() {
+        let obj = format!("{}o TEST-2\nf 1 2 3 4 5\n", POSITIONS);
+        let models = read_str("fan", &obj).unwrap();
+        assert_eq!(triangles(&models[&2]), [[0, 1, 2], [0, 2, 3], [0, 3, 4]]);
+    }
+
+    #[test]
+    fn rejects_indexes_out_of_range() {
+        let obj = format!("{}o TEST-0\nf 1 2 -6\n", POSITIONS);
+        assert!(read_str("out_of_range", &obj).is_err());
+        let obj = format!("{}o TEST-0\nf 0 1 2\n", POSITIONS);
+        assert!(read_str("zero", &obj).is_err());
+    }
+}
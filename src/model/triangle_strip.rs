@@ -1,18 +1,47 @@
-use crate::vec::{Vec2, Vec3};
+use crate::{
+    error::{Error, Result},
+    texture::Color,
+    vec::{Vec2, Vec3},
+};
 use byteorder::{ReadBytesExt, WriteBytesExt, LE};
-use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind, Read, Seek, SeekFrom, Write},
+};
 
+// each vertex takes 5 quadwords of VU memory after the 4 quadword header,
+// stay inside half of it since it is double buffered
+pub(crate) const MAX_VERTICES: usize = 100;
+
+// vertex colors are PS2 style, 0x80 is full intensity
+pub(crate) const NEUTRAL_COLOR: Color = Color {
+    r: 0x80,
+    g: 0x80,
+    b: 0x80,
+    a: 0x80,
+};
+
+/// vertices of a triangle strip, and the material it is drawn with
 pub struct TriangleStrip {
+    /// position of each vertex
     pub pos: Vec<Vec3<f32>>,
+    /// normal of each vertex, 127 is 1
     pub norm: Vec<Vec3<i8>>,
+    /// texture coordinates of each vertex
     pub uv: Vec<Vec2<f32>>,
+    /// color of each vertex, PS2 style, 0x80 is full intensity
+    pub color: Vec<Color>,
+    /// the material of the last material record before it
     pub material: u32,
+    /// bytes, where the header of this triangle strip is, 0 if it was not read from a file
+    pub offset: u64,
 }
 
 impl TriangleStrip {
-    pub fn read<T: Read + Seek>(reader: &mut T, material: &mut u32) -> io::Result<Self> {
+    /// find and read the next triangle strip, material is updated by material records on the way
+    pub fn read<T: Read + Seek>(reader: &mut T, material: &mut u32) -> Result<Self> {
         // read until 0x10 aligned
-        let reader_pos = reader.seek(SeekFrom::Current(0))?;
+        let reader_pos = reader.stream_position()?;
         let diff = 0x10 - (reader_pos % 0x10);
         if diff != 0x10 {
             reader.seek(SeekFrom::Current(diff as _))?;
@@ -21,17 +50,21 @@ impl TriangleStrip {
         reader.seek(SeekFrom::Current(8))?;
         // discard until triangle strip begins (or found material)
         // (material code should probably be outside of this function, but it is not)
+        let offset;
         loop {
             const FIND_TS_SIGNATURE: [u8; 8] = [0x00, 0x00, 0x00, 0x20, 0x40, 0x40, 0x40, 0x40];
             const FIND_MAT_SIGNATURE: [u8; 4] = [0xFE, 0xFF, 0xFF, 0xFF];
             let mut signature = [0; 8];
             let read = reader.read(&mut signature)?;
             if read == 0 {
-                println!("Model EOF!");
-                return Err(ErrorKind::UnexpectedEof.into());
+                return Err(io::Error::from(ErrorKind::UnexpectedEof).into());
             }
             match signature {
-                FIND_TS_SIGNATURE => break,
+                FIND_TS_SIGNATURE => {
+                    // the signature is the end of the first row of the header
+                    offset = reader.stream_position()? - 0x20;
+                    break;
+                }
                 _ if signature[4..] == FIND_MAT_SIGNATURE => {
                     *material = reader.read_u32::<LE>()? / 2;
                     reader.seek(SeekFrom::Current(4))?;
@@ -48,64 +81,121 @@ impl TriangleStrip {
         // read vertex normals
         let norm = TriangleStrip::read_norm(reader)?;
 
-        // read unknown
-        // println!("    - Reading vertex unknown");
-        let unknown_head = reader.read_u32::<LE>()?;
-        assert_eq!(unknown_head & 0xFF00FFFF, 0x6E00C006);
-        let unknown_count = (unknown_head >> 16 & 0xFF) as _;
-        let mut unknown = Vec::with_capacity(unknown_count);
-        for _ in 0..unknown_count {
-            unknown.push(reader.read_u32::<LE>()?);
-        }
-        if unknown != vec![0x80808080; unknown_count] {
-            // print!("STRANGE UNKNOWN: [ ");
-            // for unknown in unknown {
-            //     print!("{:x} ", unknown);
-            // }
-            // println!("]");
-        }
+        // read vertex colors
+        let color = TriangleStrip::read_color(reader)?;
 
         // read texture coordinates
         let uv = TriangleStrip::read_uv(reader)?;
 
-        // read until 0x10 aligned
-        let reader_pos = reader.seek(SeekFrom::Current(0))?;
-        let diff = 0x10 - (reader_pos % 0x10);
-        if diff != 0x10 {
-            reader.seek(SeekFrom::Current(diff as _))?;
-        }
-
         Ok(Self {
             pos,
             norm,
             uv,
+            color,
             material: *material,
+            offset,
         })
     }
 
+    /// write the strip packet, starting 0x10 aligned
     pub fn write<T: Write>(&self, writer: &mut T) -> io::Result<()> {
+        // write header, VIF codes: UNPACK (vertex count and GIF tag), STCYCL, STMASK
+        let header = [
+            0x60048000,
+            self.pos.len() as u32,
+            0x00000041,
+            0x301E4000,
+            0x301EC000,
+            0x01000105,
+            0x20000000,
+            0x40404040,
+        ];
+        for word in header {
+            writer.write_u32::<LE>(word)?;
+        }
+
         self.write_pos(writer)?;
         self.write_norm(writer)?;
 
-        // write unknown header
-        let unknown_count = self.pos.len() as u32; // probably should be same as rest for length
-        let unknown_head = 0x78008004 | (unknown_count << 16);
-        writer.write_u32::<LE>(unknown_head)?;
-        // write unknown
-        for _ in 0..unknown_count {
-            writer.write_u32::<LE>(0x80808080)?
-        }
+        self.write_color(writer)?;
 
         self.write_uv(writer)?;
 
         Ok(())
     }
 
-    fn read_pos<T: Read + Seek>(reader: &mut T) -> io::Result<Vec<Vec3<f32>>> {
-        // read vertex position header and assert it is correct
+    /// join triangles into strips of at most max_len vertices.
+    /// even triangles of a strip are wound the same way as the triangles given
+    pub fn stripify(triangles: &[[usize; 3]], max_len: usize) -> Vec<Vec<usize>> {
+        // triangles by each of their edges, in winding order
+        let mut edges = HashMap::<(usize, usize), Vec<usize>>::new();
+        for (t, tri) in triangles.iter().enumerate() {
+            for i in 0..3 {
+                edges.entry((tri[i], tri[(i + 1) % 3])).or_default().push(t);
+            }
+        }
+
+        let mut used = vec![false; triangles.len()];
+        let mut strips = Vec::new();
+        for t in 0..triangles.len() {
+            if used[t] {
+                continue;
+            }
+
+            // start with whichever rotation of the triangle makes the longest strip
+            let mut longest = (Vec::new(), Vec::new());
+            for rotation in 0..3 {
+                let tri = triangles[t];
+                let mut strip = vec![
+                    tri[rotation],
+                    tri[(rotation + 1) % 3],
+                    tri[(rotation + 2) % 3],
+                ];
+                let mut strip_triangles = vec![t];
+
+                // keep adding triangles that share the last edge
+                while strip.len() < max_len {
+                    let n = strip.len();
+                    // odd triangles are wound the other way
+                    let edge = if n.is_multiple_of(2) {
+                        (strip[n - 2], strip[n - 1])
+                    } else {
+                        (strip[n - 1], strip[n - 2])
+                    };
+                    let next = edges
+                        .get(&edge)
+                        .and_then(|ts| {
+                            ts.iter()
+                                .find(|t| !used[**t] && !strip_triangles.contains(*t))
+                        })
+                        .copied();
+                    let Some(next) = next else {
+                        break;
+                    };
+                    strip_triangles.push(next);
+                    let tri = triangles[next];
+                    let i = (0..3)
+                        .find(|i| (tri[*i], tri[(i + 1) % 3]) == edge)
+                        .unwrap();
+                    strip.push(tri[(i + 2) % 3]);
+                }
+                if strip.len() > longest.0.len() {
+                    longest = (strip, strip_triangles);
+                }
+            }
+
+            for t in longest.1 {
+                used[t] = true;
+            }
+            strips.push(longest.0);
+        }
+        strips
+    }
+
+    fn read_pos<T: Read + Seek>(reader: &mut T) -> Result<Vec<Vec3<f32>>> {
+        // read vertex position header and check it is correct
         // println!("    - Reading vertex positions");
-        let pos_head = reader.read_u32::<LE>()?;
-        assert_eq!(pos_head & 0xFF00FFFF, 0x78008004);
+        let pos_head = read_head(reader, 0x78008004, "vertex position")?;
 
         // read vertex position count from header
         let pos_count = (pos_head >> 16 & 0xFF) as _;
@@ -137,11 +227,10 @@ impl TriangleStrip {
         Ok(())
     }
 
-    fn read_norm<T: Read + Seek>(reader: &mut T) -> io::Result<Vec<Vec3<i8>>> {
-        // read vertex normal header and assert it is correct
+    fn read_norm<T: Read + Seek>(reader: &mut T) -> Result<Vec<Vec3<i8>>> {
+        // read vertex normal header and check it is correct
         // println!("    - Reading vertex normals");
-        let norm_head = reader.read_u32::<LE>()?;
-        assert_eq!(norm_head & 0xFF00FFFF, 0x7E008005);
+        let norm_head = read_head(reader, 0x7E008005, "vertex normal")?;
 
         // read vertex normal count from header
         let norm_count = (norm_head >> 16 & 0xFF) as _;
@@ -155,8 +244,16 @@ impl TriangleStrip {
                 z: reader.read_i8()?,
             });
             // normal has byte at end for padding
-            let vtx_norm_padding_byte = reader.read_i8()?;
-            assert_eq!(vtx_norm_padding_byte, 0x00);
+            let vtx_norm_padding_byte = reader.read_u8()?;
+            if vtx_norm_padding_byte != 0x00 {
+                return Err(Error::BadHeader {
+                    offset: reader.stream_position()? - 1,
+                    index: None,
+                    what: "vertex normal padding",
+                    expected: 0x00,
+                    found: vtx_norm_padding_byte as _,
+                });
+            }
         }
         Ok(norm)
     }
@@ -177,11 +274,47 @@ impl TriangleStrip {
         Ok(())
     }
 
-    fn read_uv<T: Read + Seek>(reader: &mut T) -> io::Result<Vec<Vec2<f32>>> {
-        // read vertex texture coordinate header and assert it is correct
+    fn read_color<T: Read + Seek>(reader: &mut T) -> Result<Vec<Color>> {
+        // read vertex color header and check it is correct
+        let color_head = read_head(reader, 0x6E00C006, "vertex color")?;
+
+        // read vertex color count from header
+        let color_count = (color_head >> 16 & 0xFF) as _;
+        let mut color = Vec::with_capacity(color_count);
+
+        // read vertex colors, usually 0x80808080
+        for _ in 0..color_count {
+            color.push(Color {
+                r: reader.read_u8()?,
+                g: reader.read_u8()?,
+                b: reader.read_u8()?,
+                a: reader.read_u8()?,
+            });
+        }
+        Ok(color)
+    }
+
+    fn write_color<T: Write>(&self, writer: &mut T) -> io::Result<()> {
+        // write vertex colors header, one for each vertex
+        let color_count = self.pos.len() as u32;
+        let color_head = 0x6E00C006 | (color_count << 16);
+        writer.write_u32::<LE>(color_head)?;
+
+        // write vertex colors
+        for i in 0..self.pos.len() {
+            let color = self.color.get(i).unwrap_or(&NEUTRAL_COLOR);
+            writer.write_u8(color.r)?;
+            writer.write_u8(color.g)?;
+            writer.write_u8(color.b)?;
+            writer.write_u8(color.a)?;
+        }
+        Ok(())
+    }
+
+    fn read_uv<T: Read + Seek>(reader: &mut T) -> Result<Vec<Vec2<f32>>> {
+        // read vertex texture coordinate header and check it is correct
         // println!("    - Reading vertex texture coordinates");
-        let uv_head = reader.read_u32::<LE>()?;
-        assert_eq!(uv_head & 0xFF00FFFF, 0x64008007);
+        let uv_head = read_head(reader, 0x64008007, "vertex texture coordinate")?;
 
         // read texture coordinate count from header
         let uv_count = (uv_head >> 16 & 0xFF) as _;
@@ -198,12 +331,12 @@ impl TriangleStrip {
     }
 
     fn write_uv<T: Write>(&self, writer: &mut T) -> io::Result<()> {
-        // write vertex positions header
+        // write vertex texture coordinates header
         let uv_count = self.uv.len() as u32;
-        let uv_head = 0x78008004 | (uv_count << 16);
+        let uv_head = 0x64008007 | (uv_count << 16);
         writer.write_u32::<LE>(uv_head)?;
 
-        // write vertex positions
+        // write vertex texture coordinates
         for uv in &self.uv {
             writer.write_f32::<LE>(uv.x)?;
             writer.write_f32::<LE>(uv.y)?;
@@ -211,3 +344,93 @@ impl TriangleStrip {
         Ok(())
     }
 }
+
+// read an UNPACK header, the count in its third byte can be anything
+fn read_head<T: Read + Seek>(reader: &mut T, expected: u32, what: &'static str) -> Result<u32> {
+    let head = reader.read_u32::<LE>()?;
+    if head & 0xFF00FFFF != expected {
+        return Err(Error::BadHeader {
+            offset: reader.stream_position()? - 4,
+            index: None,
+            what,
+            expected,
+            found: head,
+        });
+    }
+    Ok(head)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the triangles of a strip, odd triangles wound back the other way,
+    // each rotated so its smallest vertex is first
+    fn triangles(strip: &[usize]) -> Vec<[usize; 3]> {
+        strip
+            .windows(3)
+            .enumerate()
+            .map(|(i, w)| {
+                let tri = if i % 2 == 0 {
+                    [w[0], w[1], w[2]]
+                } else {
+                    [w[1], w[0], w[2]]
+                };
+                rotate(tri)
+            })
+            .collect()
+    }
+
+    fn rotate(tri: [usize; 3]) -> [usize; 3] {
+        let i = (0..3).min_by_key(|i| tri[*i]).unwrap();
+        [tri[i], tri[(i + 1) % 3], tri[(i + 2) % 3]]
+    }
+
+    // a row of quads, 2 triangles each, with vertices 0, 2, 4.. on top and 1, 3, 5.. below
+    fn quads(len: usize) -> Vec<[usize; 3]> {
+        (0..len)
+            .flat_map(|i| {
+                let (a, b, c, d) = (i * 2, i * 2 + 1, i * 2 + 2, i * 2 + 3);
+                [[a, b, c], [c, b, d]]
+            })
+            .collect()
+    }
+
+    fn sorted(mut triangles: Vec<[usize; 3]>) -> Vec<[usize; 3]> {
+        triangles.sort_unstable();
+        triangles
+    }
+
+    #[test]
+    fn strips_keep_the_winding_of_each_triangle() {
+        let input = quads(4);
+        let strips = TriangleStrip::stripify(&input, MAX_VERTICES);
+        assert_eq!(strips.len(), 1);
+        assert_eq!(strips[0].len(), 10);
+        let output = strips.iter().flat_map(|s| triangles(s)).collect();
+        let expected = input.into_iter().map(rotate).collect();
+        assert_eq!(sorted(output), sorted(expected));
+    }
+
+    #[test]
+    fn strips_are_split_at_max_len() {
+        let input = quads(10);
+        let strips = TriangleStrip::stripify(&input, 6);
+        assert!(strips.len() > 1);
+        assert!(strips.iter().all(|s| s.len() <= 6));
+        let output = strips.iter().flat_map(|s| triangles(s)).collect();
+        let expected = input.into_iter().map(rotate).collect();
+        assert_eq!(sorted(output), sorted(expected));
+    }
+
+    #[test]
+    fn triangles_wound_the_other_way_start_a_new_strip() {
+        // the second triangle shares an edge with the first in the same direction
+        let input = vec![[0, 1, 2], [1, 2, 3]];
+        let strips = TriangleStrip::stripify(&input, MAX_VERTICES);
+        assert_eq!(strips.len(), 2);
+        let output = strips.iter().flat_map(|s| triangles(s)).collect();
+        let expected = input.into_iter().map(rotate).collect();
+        assert_eq!(sorted(output), sorted(expected));
+    }
+}
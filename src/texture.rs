@@ -1,147 +1,728 @@
 use image::imageops;
+use image::DynamicImage;
 use image::GenericImageView;
 use image::ImageBuffer;
+use image::ImageOutputFormat;
 use image::Rgba;
+use itertools::Itertools;
 use ndarray::Array2;
 
+use self::alpha::{AlphaPolicy, AlphaReport};
+use self::indexed::Indexed;
 use self::palette::Palette;
 use self::palette_texture::PaletteTexture;
+use self::quantize::{ColorError, Dither};
 use self::texture_format::TextureFormat;
 use self::texture_format::TextureFormats;
+use self::texture_header::GS_BLOCKS;
+use crate::{
+    error::{Error, Index, Result},
+    model::{
+        material::{resize_tex0s, tex0_offsets},
+        Models,
+    },
+    vec::Vec2,
+};
 use std::collections::HashMap;
-use std::fs::File;
-use std::fs::OpenOptions;
-use std::io::{self, BufWriter, ErrorKind, Read, Seek, Write};
+use std::fmt;
+use std::io::{Cursor, Read, Seek, Write};
+use std::ops::Range;
 use std::path::Path;
 
+/// fit alpha into 16-bit and 32-bit palettes
+pub mod alpha;
+/// unscramble and scramble 4-bit textures
 pub mod convert_4bit;
+/// unscramble and scramble 8-bit textures
 pub mod convert_8bit;
-mod palette;
-mod palette_texture;
-pub mod test;
+/// indexed pngs, palette indexes with the palette they index
+pub mod indexed;
+/// colors of textures
+pub mod palette;
+/// palette indexes of textures
+pub mod palette_texture;
+/// reduce the colors of textures
+pub mod quantize;
+/// generated palettes and textures to test the converters with
+#[cfg(test)]
+#[allow(dead_code)]
+mod test;
+/// where textures are in binary files, and how they are stored
 pub mod texture_format;
+/// the GS uploads of textures and palettes, decoded by field
+pub mod texture_header;
 
-// todo: flag
-const VERBOSE: bool = false;
+/// which texture of which bank of texture formats
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TextureId {
+    /// the bank of texture formats, in the order they are stored
+    pub bank: usize,
+    /// the texture format, in the order they are kept in its bank
+    pub index: usize,
+}
+
+impl TextureId {
+    /// a texture of the first bank
+    pub fn first_bank(index: usize) -> Self {
+        Self { bank: 0, index }
+    }
+
+    /// from what is after `<stem>-` in a file name, `<i>` for the first bank, or `<bank>-<i>`
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.split_once('-') {
+            Some((bank, index)) => Some(Self {
+                bank: bank.parse().ok()?,
+                index: index.parse().ok()?,
+            }),
+            None => Some(Self::first_bank(name.parse().ok()?)),
+        }
+    }
+
+    /// which texture this is, for errors
+    pub fn error_index(&self) -> Index {
+        match self.bank {
+            0 => Index::Texture(self.index),
+            bank => Index::BankTexture(bank, self.index),
+        }
+    }
+}
+
+// what is after <stem>- in file names, the first bank keeps the names it had without banks
+impl fmt::Display for TextureId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.bank {
+            0 => write!(f, "{}", self.index),
+            bank => write!(f, "{}-{}", bank, self.index),
+        }
+    }
+}
 
+/// something about a replacement that was written differently than it was given,
+/// or that the game may not draw as expected
+#[derive(Debug, Clone, Copy)]
+pub enum Notice {
+    /// the texture format was resized to fit the replacement
+    Resized {
+        /// original size
+        from: Vec2<u32>,
+        /// size of the replacement
+        to: Vec2<u32>,
+    },
+    /// the palette of an indexed replacement has more colors than fit,
+    /// so it was written like any other image
+    PaletteNotKept {
+        /// colors of the palette
+        colors: usize,
+        /// colors that fit
+        max: usize,
+    },
+    /// the replacement was quantized to fit its palette
+    Quantized {
+        /// colors that fit
+        max: usize,
+        /// how far the quantized texture is from the replacement
+        error: ColorError,
+    },
+    /// the texture and palette moved, but the header does not say where they are,
+    /// so the game may look in the old place
+    Unaddressed {
+        /// bytes, where the texture and palette were
+        from: [u32; 2],
+        /// bytes, where the texture and palette are now
+        to: [u32; 2],
+    },
+    /// the texture was resized, but no material record draws with it,
+    /// so the size it is drawn with was not updated
+    NotDrawn,
+}
+
+impl fmt::Display for Notice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Notice::Resized { from, to } => write!(f, "resizing from {} to {}", from, to),
+            Notice::PaletteNotKept { colors, max } => write!(
+                f,
+                "palette of {} colors does not fit in {}, not kept",
+                colors, max
+            ),
+            Notice::Quantized { max, error } => write!(
+                f,
+                "reduced {} colors to {}, error: mean {:.2}, max {:.2}",
+                error.colors, max, error.mean, error.max
+            ),
+            Notice::Unaddressed { from, to } => write!(
+                f,
+                "texture and palette moved from {:#x} and {:#x} to {:#x} and {:#x}, \
+                but its header does not say where they are, so it can not be updated",
+                from[0], from[1], to[0], to[1]
+            ),
+            Notice::NotDrawn => write!(
+                f,
+                "no material record draws with it, so the size it is drawn with was not updated"
+            ),
+        }
+    }
+}
+
+/// what importing replacements into a binary file did
+#[derive(Default)]
+pub struct ImportReport {
+    /// texture formats of the original, and the ones that were rejected
+    pub banks: Vec<TextureFormats>,
+    /// what is worth knowing about each replacement, in the order it was written
+    pub notices: Vec<(TextureId, Notice)>,
+    /// how the alpha of each replacement was changed
+    pub alpha: Vec<(TextureId, AlphaReport)>,
+}
+
+/// textures of a binary file, by bank and index,
+/// and the texture formats of each bank they were read with, and the ones that were rejected
 #[derive(Default)]
-pub struct Textures(pub HashMap<usize, Texture>);
+pub struct Textures(pub HashMap<TextureId, Texture>, pub Vec<TextureFormats>);
 
 impl Textures {
-    pub fn read_from_bin(path: &Path) -> io::Result<Self> {
-        let mut reader = File::open(path)?;
-
-        let tfs = match TextureFormats::read(&mut reader, VERBOSE) {
-            Ok(tfs) => tfs,
-            Err(e) => {
-                if e.kind() == ErrorKind::UnexpectedEof {
-                    return Ok(Self(HashMap::new()));
-                } else {
-                    return Err(e);
-                }
-            }
-        };
+    /// read every texture of a binary file, none if it has no texture formats
+    pub fn read_from_bin(path: &Path) -> Result<Self> {
+        Self::read(&std::fs::read(path)?)
+    }
+
+    /// read every texture of every bank of the contents of a binary file,
+    /// none if it has no texture formats
+    pub fn read(data: &[u8]) -> Result<Self> {
+        let mut reader = Cursor::new(data);
+        let banks = TextureFormats::read_banks(data)?;
 
         let mut textures = HashMap::new();
-        for tf in tfs.0.iter() {
-            let texture = Texture::read_from_bin(&mut reader, *tf)?;
-            textures.insert(textures.len(), texture);
+        for (bank, tfs) in banks.iter().enumerate() {
+            for (index, tf) in tfs.0.iter().enumerate() {
+                let id = TextureId { bank, index };
+                let texture = Texture::read_from_bin(&mut reader, *tf)
+                    .map_err(|e| e.with_index(id.error_index()))?;
+                textures.insert(id, texture);
+            }
         }
-        Ok(Self(textures))
+        Ok(Self(textures, banks))
     }
 
-    pub fn write_to_bin(&self, original: &Path) -> io::Result<()> {
+    /// if quantize is set, textures with too many colors are reduced to fit their palette.
+    /// alpha says what happens to partly transparent pixels in 16-bit palettes.
+    /// replacements can be a different size than the original texture,
+    /// the textures after it are moved to make room, and later banks with them.
+    /// the copy is written into out_dir.
+    /// the copy is read back to make sure the replacements read back as they were written,
+    /// and nothing else changed
+    pub fn write_to_bin(
+        &self,
+        original: &Path,
+        out_dir: &Path,
+        quantize: Option<Dither>,
+        alpha: AlphaPolicy,
+    ) -> Result<ImportReport> {
         // create dir if it does not exist
-        let out_dir = Path::new("in/nights.test.nightsintobins/Redirector/afs/");
         if !out_dir.exists() {
             std::fs::create_dir_all(out_dir)?;
         }
 
-        // copy original file
-        let copy = out_dir.join(original.file_name().unwrap());
-        std::fs::copy(original, &copy)?;
+        // read original file and texture formats
+        let data = std::fs::read(original)?;
+        let banks = TextureFormats::read_banks(&data)?;
+        let mut notices = Vec::new();
 
-        // read texture formats
-        let mut file = OpenOptions::new().read(true).write(true).open(copy)?;
-        let tfs = TextureFormats::read(&mut file, false)?;
+        // resize texture formats to fit their replacements
+        let mut new_banks = banks.iter().map(|tfs| tfs.0.clone()).collect::<Vec<_>>();
+        for (id, t) in self.0.iter() {
+            let tf = new_banks
+                .get_mut(id.bank)
+                .and_then(|tfs| tfs.get_mut(id.index))
+                .ok_or(Error::NotFound {
+                    index: id.error_index(),
+                })?;
+            let size = Vec2 {
+                x: t.0.ncols() as u32,
+                y: t.0.nrows() as u32,
+            };
+            if size != tf.size {
+                notices.push((
+                    *id,
+                    Notice::Resized {
+                        from: tf.size,
+                        to: size,
+                    },
+                ));
+                tf.size = size;
+                tf.validate_size()
+                    .map_err(|e| e.with_index(id.error_index()))?;
+            }
+        }
+
+        // copy original file, moving textures and palettes to make room for resized textures.
+        // they are copied in the order they are stored, which is not always the order of the
+        // texture formats. unchanged has where each copied range of the original is in the copy
+        let mut blocks = Vec::new();
+        for (bank, tfs) in banks.iter().enumerate() {
+            for (index, tf) in tfs.0.iter().enumerate() {
+                let [texture, palette] = tf.ranges();
+                blocks.push((texture, bank, index, false));
+                blocks.push((palette, bank, index, true));
+            }
+        }
+        blocks.sort_unstable_by_key(|(range, ..)| range.start);
+        let mut unchanged = Vec::new();
+        let mut copy = Vec::with_capacity(data.len());
+        let mut position = 0;
+        for (range, bank, index, is_palette) in blocks {
+            let (start, end) = (range.start as usize, range.end as usize);
+            if start > position {
+                // estimated locations are aligned to 0x100 after the texture formats,
+                // pad to keep them aligned after a resize
+                let pad = (position as isize - copy.len() as isize).rem_euclid(0x100);
+                copy.resize(copy.len() + pad as usize, 0);
+                unchanged.push((position..start, copy.len()));
+                copy.extend_from_slice(&data[position..start]);
+            }
+            let tf = banks[bank].0[index];
+            let new_tf = &mut new_banks[bank][index];
+            if is_palette {
+                new_tf.palette_location = copy.len() as u32;
+            } else {
+                new_tf.location = copy.len() as u32;
+            }
+            if is_palette || tf.size == new_tf.size {
+                unchanged.push((start..end, copy.len()));
+                copy.extend_from_slice(&data[start..end]);
+            } else {
+                copy.resize(copy.len() + new_tf.texture_len() as usize, 0);
+            }
+            position = end;
+        }
+        unchanged.push((position..data.len(), copy.len()));
+        copy.extend_from_slice(&data[position..]);
+        let mut file = Cursor::new(copy);
+
+        // texture formats are never inside textures, so they are in an unchanged range
+        for (tfs, new_tfs) in banks.iter().zip(new_banks.iter_mut()) {
+            for (tf, new_tf) in tfs.0.iter().zip(new_tfs.iter_mut()) {
+                let header = tf.header as usize;
+                if let Some((range, copy_start)) =
+                    unchanged.iter().find(|(range, _)| range.contains(&header))
+                {
+                    new_tf.header = (copy_start + header - range.start) as u32;
+                }
+            }
+        }
+
+        // update headers of resized textures, and of moved textures if they say where they are
+        let rewritten = |tf: &TextureFormat, new_tf: &TextureFormat| {
+            tf.size != new_tf.size
+                || (new_tf.addressed
+                    && (tf.location, tf.palette_location)
+                        != (new_tf.location, new_tf.palette_location))
+        };
+        for (bank, (tfs, new_tfs)) in banks.iter().zip(new_banks.iter()).enumerate() {
+            for (index, (tf, new_tf)) in tfs.0.iter().zip(new_tfs.iter()).enumerate() {
+                if rewritten(tf, new_tf) {
+                    new_tf.write(&mut file)?;
+                }
+                // the header has no address to update, so the game may look in the old place
+                let moved = (tf.location, tf.palette_location)
+                    != (new_tf.location, new_tf.palette_location);
+                if moved && !new_tf.addressed {
+                    notices.push((
+                        TextureId { bank, index },
+                        Notice::Unaddressed {
+                            from: [tf.location, tf.palette_location],
+                            to: [new_tf.location, new_tf.palette_location],
+                        },
+                    ));
+                }
+            }
+        }
+        let original_banks = banks;
+        let banks = new_banks
+            .into_iter()
+            .map(|tfs| TextureFormats(tfs, Vec::new(), Vec::new()))
+            .collect::<Vec<_>>();
+        check_gs_memory(&original_banks, &banks)?;
 
         // write textures
-        for (i, t) in self.0.iter() {
-            t.write_to_bin(&mut file, tfs.0[*i])?;
+        let mut reports = Vec::new();
+        let mut written = HashMap::new();
+        for (id, t) in self.0.iter().sorted_by_key(|(id, _)| **id) {
+            let tf = banks[id.bank].0[id.index];
+            let max_len = 2usize.pow(tf.pixel_encoding as _);
+            // indexed replacements are written index for index if their palette fits,
+            // otherwise they are written like any other image
+            let indexed = t.1.as_ref().filter(|indexed| {
+                let fits = indexed.palette.0.len() <= max_len;
+                if !fits {
+                    notices.push((
+                        *id,
+                        Notice::PaletteNotKept {
+                            colors: indexed.palette.0.len(),
+                            max: max_len,
+                        },
+                    ));
+                }
+                fits
+            });
+            let t = Texture(t.0.clone(), indexed.cloned());
+            // alpha first, fewer alpha values means fewer colors
+            let (stored, report) = t.store_alpha(tf.color_depth, alpha);
+            if alpha == AlphaPolicy::Reject && tf.color_depth == 16 && report.changed > 0 {
+                return Err(Error::PartialAlpha {
+                    offset: tf.location as _,
+                    index: Some(id.error_index()),
+                    pixels: report.changed,
+                });
+            }
+            let stored = if let (Some(dither), None) = (quantize, &stored.1) {
+                let (stored, error) =
+                    stored.quantize_stored(max_len, dither, tf.color_depth, report.policy);
+                if error.colors > max_len {
+                    notices.push((
+                        *id,
+                        Notice::Quantized {
+                            max: max_len,
+                            error,
+                        },
+                    ));
+                }
+                stored
+            } else {
+                stored
+            };
+            // the alpha that is written, after quantizing too
+            reports.push((
+                *id,
+                alpha::report(&t.0, &stored.0, tf.color_depth, report.policy),
+            ));
+            let t = stored;
+            t.write_to_bin(&mut file, tf)
+                .map_err(|e| e.with_index(id.error_index()))?;
+            written.insert(*id, t);
         }
-        Ok(())
+
+        // everything the textures were written over, and the headers that were rewritten
+        let mut patched = Vec::new();
+        for (bank, tfs) in banks.iter().enumerate() {
+            for (index, tf) in tfs.0.iter().enumerate() {
+                if written.contains_key(&TextureId { bank, index }) {
+                    patched.extend(tf.ranges().map(|r| r.start as _..r.end as _));
+                }
+                if rewritten(&original_banks[bank].0[index], tf) {
+                    patched.extend(
+                        tf.written_ranges()
+                            .into_iter()
+                            .map(|r| r.start as _..r.end as _),
+                    );
+                }
+            }
+        }
+        // material records draw resized textures with their new size. they are found
+        // before any is changed, so a changed TEX0 is not taken for another texture
+        let resized = resized(&original_banks, &banks).collect::<Vec<_>>();
+        if !resized.is_empty() {
+            let models = Models::read(file.get_ref())?;
+            let tex0s = resized
+                .iter()
+                .map(|(_, tf, _)| tex0_offsets(file.get_ref(), &models, tf))
+                .collect::<Vec<_>>();
+            for ((id, _, new_tf), offsets) in resized.iter().zip(tex0s) {
+                if offsets.is_empty() {
+                    notices.push((*id, Notice::NotDrawn));
+                }
+                resize_tex0s(file.get_mut(), &offsets, new_tf);
+                patched.extend(offsets.iter().map(|offset| *offset..*offset + 8));
+            }
+        }
+
+        let file = file.into_inner();
+        verify(
+            &data,
+            &file,
+            &original_banks,
+            &banks,
+            &written,
+            &unchanged,
+            &patched,
+        )?;
+
+        // write copy
+        std::fs::write(out_dir.join(original.file_name().unwrap()), file)?;
+        Ok(ImportReport {
+            banks: original_banks,
+            notices,
+            alpha: reports,
+        })
     }
 
-    pub fn write_to_image(&self, file_path: &Path) -> io::Result<()> {
+    /// write each texture to `<out_dir>/<stem>/<stem>-<i>.png` for the first bank,
+    /// or `<stem>-<bank>-<i>.png` for later banks.
+    /// if indexed is set, they are written as indexed pngs with the palette they are stored with
+    pub fn write_to_image(&self, file_path: &Path, out_dir: &Path, indexed: bool) -> Result<()> {
         if self.0.is_empty() {
             return Ok(());
         }
         // create dir if it does not exist
         let stem = file_path.file_stem().unwrap().to_str().unwrap();
-        let dir_path = format!("out/{}", stem);
-        let dir_path = Path::new(&dir_path);
+        let dir_path = out_dir.join(stem);
         if !dir_path.exists() {
-            std::fs::create_dir_all(dir_path)?;
+            std::fs::create_dir_all(&dir_path)?;
         }
         // iterate through each texture
-        for (i, texture) in self.0.iter() {
+        for (id, texture) in self.0.iter() {
             // create file and write to png
-            texture.write_to_image(Path::new(&format!("out/{}/{}-{}.png", stem, stem, i)), true);
+            let path = dir_path.join(format!("{}-{}.png", stem, id));
+            match (indexed, &texture.1) {
+                (true, Some(indexed)) => indexed.write_to_image(&path, true)?,
+                _ => texture.write_to_image(&path, true)?,
+            }
         }
         Ok(())
     }
+}
+// read the copy back, and make sure each written texture reads back as it was written, and
+// that every byte of the original outside of the patched ranges is still the same
+fn verify(
+    original: &[u8],
+    copy: &[u8],
+    original_banks: &[TextureFormats],
+    banks: &[TextureFormats],
+    written: &HashMap<TextureId, Texture>,
+    unchanged: &[(Range<usize>, usize)],
+    patched: &[Range<usize>],
+) -> Result<()> {
+    let mut reader = Cursor::new(copy);
+    let read_banks = TextureFormats::read_banks(copy)?;
+    let offset = |bank: usize| {
+        banks
+            .get(bank)
+            .and_then(|tfs| tfs.0.first())
+            .map_or(0, |tf| tf.header as _)
+    };
+    if read_banks.len() != banks.len() {
+        return Err(Error::Unverified {
+            offset: offset(read_banks.len().min(banks.len())),
+            index: None,
+            what: format!(
+                "{} texture format banks read back, expected {}",
+                read_banks.len(),
+                banks.len()
+            ),
+        });
+    }
 
-    pub fn write_to_mtl(&self, file_path: &Path) -> io::Result<()> {
-        if self.0.is_empty() {
-            return Ok(());
+    for (bank, (tfs, read_tfs)) in banks.iter().zip(read_banks.iter()).enumerate() {
+        if read_tfs.0.len() != tfs.0.len() {
+            return Err(Error::Unverified {
+                offset: offset(bank),
+                index: None,
+                what: format!(
+                    "{} texture formats read back, expected {}",
+                    read_tfs.0.len(),
+                    tfs.0.len()
+                ),
+            });
         }
+        for (index, (tf, read_tf)) in tfs.0.iter().zip(read_tfs.0.iter()).enumerate() {
+            let read_back = (read_tf.size, read_tf.location, read_tf.palette_location);
+            if (tf.size, tf.location, tf.palette_location) != read_back {
+                return Err(Error::Unverified {
+                    offset: tf.header as _,
+                    index: Some(TextureId { bank, index }.error_index()),
+                    what: format!(
+                        "texture format reads back as size {} at {:#x} with its palette at {:#x}, \
+                        expected size {} at {:#x} with its palette at {:#x}",
+                        read_tf.size,
+                        read_tf.location,
+                        read_tf.palette_location,
+                        tf.size,
+                        tf.location,
+                        tf.palette_location
+                    ),
+                });
+            }
+        }
+    }
+    for (id, t) in written.iter() {
+        let tf = read_banks[id.bank].0[id.index];
+        let read = Texture::read_from_bin(&mut reader, tf)?;
+        let different =
+            t.0.iter()
+                .zip(read.0.iter())
+                .filter(|(color, read)| palette::stored(**color, tf.color_depth) != **read)
+                .count();
+        if different > 0 {
+            return Err(Error::Unverified {
+                offset: tf.location as _,
+                index: Some(id.error_index()),
+                what: format!(
+                    "{} pixels read back different from the replacement",
+                    different
+                ),
+            });
+        }
+    }
 
-        let stem = file_path.file_stem().unwrap().to_str().unwrap();
-        let mtl_filename = format!("out/{}/{}.mtl", stem, stem);
-
-        // write mtl file
-        let mut mtl = File::create(mtl_filename)?;
+    // resized textures are uploaded where they fit, and drawn with their new size
+    check_gs_memory(original_banks, &read_banks).map_err(|e| Error::Unverified {
+        offset: 0,
+        index: None,
+        what: e.to_string(),
+    })?;
+    let resized = resized(original_banks, &read_banks).collect::<Vec<_>>();
+    if !resized.is_empty() {
+        let (models, read_models) = (Models::read(original)?, Models::read(copy)?);
+        for (id, tf, read_tf) in resized {
+            let expected = tex0_offsets(original, &models, tf).len();
+            let found = tex0_offsets(copy, &read_models, read_tf).len();
+            if found != expected {
+                return Err(Error::Unverified {
+                    offset: read_tf.header as _,
+                    index: Some(id.error_index()),
+                    what: format!(
+                        "{} material records draw with the resized texture, expected {}",
+                        found, expected
+                    ),
+                });
+            }
+        }
+    }
 
-        for i in 0..self.0.len() {
-            writeln!(mtl, "newmtl {}-{}", &stem, i)?;
-            writeln!(mtl, "map_Kd {}-{}.png", &stem, i)?;
+    let mut is_patched = vec![false; copy.len()];
+    for range in patched {
+        is_patched[range.start.min(copy.len())..range.end.min(copy.len())].fill(true);
+    }
+    for (range, copy_start) in unchanged {
+        for (offset, byte) in original[range.clone()].iter().enumerate() {
+            let copy_offset = copy_start + offset;
+            if is_patched.get(copy_offset) == Some(&true) {
+                continue;
+            }
+            if copy.get(copy_offset) != Some(byte) {
+                return Err(Error::Unverified {
+                    offset: copy_offset as _,
+                    index: None,
+                    what: format!(
+                        "byte {:#x} of the original changed outside of the replaced textures",
+                        range.start + offset
+                    ),
+                });
+            }
         }
+    }
+    Ok(())
+}
 
-        Ok(())
+// the texture formats whose size changed, with what they were and what they are
+fn resized<'a>(
+    original_banks: &'a [TextureFormats],
+    banks: &'a [TextureFormats],
+) -> impl Iterator<Item = (TextureId, &'a TextureFormat, &'a TextureFormat)> {
+    original_banks
+        .iter()
+        .zip(banks)
+        .enumerate()
+        .flat_map(|(bank, (tfs, new_tfs))| {
+            tfs.0
+                .iter()
+                .zip(new_tfs.0.iter())
+                .enumerate()
+                .map(move |(index, (tf, new_tf))| (TextureId { bank, index }, tf, new_tf))
+        })
+        .filter(|(_, tf, new_tf)| tf.size != new_tf.size)
+}
+
+// resized textures must not be uploaded past the end of GS memory, or over a texture
+// or palette they were not already uploaded over. uploads that already overlapped,
+// like textures of different banks, must be uploaded at different times
+fn check_gs_memory(original_banks: &[TextureFormats], banks: &[TextureFormats]) -> Result<()> {
+    // the blocks of the texture and of the palette of each texture format
+    let blocks = |banks: &[TextureFormats]| {
+        banks
+            .iter()
+            .enumerate()
+            .flat_map(|(bank, tfs)| {
+                tfs.0.iter().enumerate().map(move |(index, tf)| {
+                    let blocks = [tf.texture_upload().blocks(), tf.gs.palette.blocks()];
+                    (TextureId { bank, index }, blocks)
+                })
+            })
+            .collect::<Vec<_>>()
+    };
+    let (before, after) = (blocks(original_banks), blocks(banks));
+
+    for (id, tf, new_tf) in resized(original_banks, banks) {
+        let i = before.iter().position(|(other, _)| *other == id).unwrap();
+        let (Some(old), Some(new)) = (&before[i].1[0], &after[i].1[0]) else {
+            continue;
+        };
+        let error = |what: String| Error::GsOverlap {
+            offset: tf.header as _,
+            index: Some(id.error_index()),
+            size: new_tf.size,
+            what,
+        };
+        if new.last().is_some_and(|block| *block >= GS_BLOCKS) {
+            return Err(error("past the end of GS memory".to_string()));
+        }
+        for (j, (other, _)) in before.iter().enumerate() {
+            for (k, what) in ["texture", "palette"].into_iter().enumerate() {
+                if (j, k) == (i, 0) {
+                    continue;
+                }
+                let (Some(other_old), Some(other_new)) = (&before[j].1[k], &after[j].1[k]) else {
+                    continue;
+                };
+                if !new.is_disjoint(other_new) && old.is_disjoint(other_old) {
+                    let other = other.error_index();
+                    return Err(error(format!("over the {} of {}", what, other)));
+                }
+            }
+        }
     }
+    Ok(())
 }
-pub struct Texture(pub Array2<Color>);
+
+/// decoded pixels, by row, and the palette indexes they were decoded from if they are known
+pub struct Texture(pub Array2<Color>, pub Option<Indexed>);
 
 impl Texture {
-    pub fn read_from_bin<T: Read + Seek>(reader: &mut T, tf: TextureFormat) -> io::Result<Self> {
+    /// read and decode the palette and palette texture of a texture format
+    pub fn read_from_bin<T: Read + Seek>(reader: &mut T, tf: TextureFormat) -> Result<Self> {
         let palette = Palette::read_from_bin(reader, tf)?;
         let palette_tex = PaletteTexture::read_from_bin(reader, tf)?;
 
-        Ok(Self::from_palette_and_palette_texture(
-            &palette,
-            &palette_tex,
-        ))
+        Ok(Self::from_indexed(Indexed {
+            palette,
+            indexes: palette_tex,
+        }))
     }
 
-    pub fn write_to_bin<T: Write + Seek>(
-        &self,
-        writer: &mut T,
-        tf: TextureFormat,
-    ) -> io::Result<()> {
-        let (pal, pal_tex) = self.to_palette_and_palette_texture(tf);
+    /// encode and write the palette and palette texture of a texture format
+    pub fn write_to_bin<T: Write + Seek>(&self, writer: &mut T, tf: TextureFormat) -> Result<()> {
+        let (pal, pal_tex) = self.to_palette_and_palette_texture(tf)?;
         pal.write_to_bin(writer, tf)?;
         pal_tex.write_to_bin(writer, tf)
     }
 
+    /// colors of the palette texture looked up in the palette
     pub fn from_palette_and_palette_texture(
         palette: &Palette,
         palette_tex: &PaletteTexture,
     ) -> Self {
-        Self(palette_tex.0.map(|i| palette.0[*i as usize]))
+        Self::from_indexed(Indexed {
+            palette: palette.clone(),
+            indexes: palette_tex.clone(),
+        })
     }
 
-    /// image should be flipped, unless you are testing something
-    pub fn write_to_image(&self, path: &Path, flip: bool) {
+    /// colors of the indexes looked up in the palette, keeping both
+    pub fn from_indexed(indexed: Indexed) -> Self {
+        Self(indexed.pixels(), Some(indexed))
+    }
+
+    fn to_image(&self) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
         let (width, height) = (self.0.ncols(), self.0.nrows());
         let mut img = ImageBuffer::new(width as u32, height as u32);
 
@@ -152,16 +733,36 @@ impl Texture {
                 img.put_pixel(x as u32, y as u32, pixel);
             }
         }
+        img
+    }
+
+    /// image should be flipped, unless you are testing something
+    pub fn write_to_image(&self, path: &Path, flip: bool) -> Result<()> {
+        let mut img = self.to_image();
         if flip {
             imageops::flip_vertical_in_place(&mut img);
         }
-        img.save(path).unwrap();
+        img.save(path)?;
+        Ok(())
     }
 
+    /// png file contents, not flipped
+    pub fn to_png(&self) -> Result<Vec<u8>> {
+        let mut png = Cursor::new(Vec::new());
+        DynamicImage::ImageRgba8(self.to_image()).write_to(&mut png, ImageOutputFormat::Png)?;
+        Ok(png.into_inner())
+    }
+
+    /// indexed pngs keep their palette indexes.
     /// image should be flipped, unless you are testing something
-    pub fn read_from_image(path: &Path, flip: bool) -> Texture {
-        let mut img = image::open(path).unwrap();
-        imageops::flip_vertical_in_place(&mut img);
+    pub fn read_from_image(path: &Path, flip: bool) -> Result<Texture> {
+        if let Some(indexed) = Indexed::read_from_image(path, flip)? {
+            return Ok(Self::from_indexed(indexed));
+        }
+        let mut img = image::open(path)?;
+        if flip {
+            imageops::flip_vertical_in_place(&mut img);
+        }
         let (width, height) = img.dimensions();
         let mut data = Array2::default((height as usize, width as usize));
 
@@ -175,11 +776,79 @@ impl Texture {
             };
         }
 
-        Texture(data)
+        Ok(Texture(data, None))
+    }
+
+    /// reduce to at most max_len colors
+    pub fn quantize(&self, max_len: usize, dither: Dither) -> (Self, ColorError) {
+        let (quantized, error) = quantize::quantize(&self.0, max_len, dither);
+        (Self(quantized, None), error)
+    }
+
+    /// reduce to at most max_len colors, for a texture whose alpha is already stored for a
+    /// palette of color_depth. median cut averages alpha too, so it is stored again after,
+    /// thresholded instead of dithered so it does not add colors
+    pub fn quantize_stored(
+        &self,
+        max_len: usize,
+        dither: Dither,
+        color_depth: u8,
+        policy: AlphaPolicy,
+    ) -> (Self, ColorError) {
+        let (quantized, error) = self.quantize(max_len, dither);
+        let policy = match policy {
+            AlphaPolicy::Dither => AlphaPolicy::default(),
+            policy => policy,
+        };
+        let (stored, _) = quantized.store_alpha(color_depth, policy);
+        (stored, error)
+    }
+
+    /// change alpha to what a palette of color_depth can store
+    /// indexed textures change the alpha of their palette instead, which can not be dithered,
+    /// so they are thresholded at the default instead
+    pub fn store_alpha(&self, color_depth: u8, policy: AlphaPolicy) -> (Self, AlphaReport) {
+        let Some(indexed) = &self.1 else {
+            let (stored, report) = alpha::store(&self.0, color_depth, policy);
+            return (Self(stored, None), report);
+        };
+        let policy = match policy {
+            AlphaPolicy::Dither => AlphaPolicy::default(),
+            policy => policy,
+        };
+        let palette =
+            Array2::from_shape_vec((1, indexed.palette.0.len()), indexed.palette.0.clone())
+                .unwrap();
+        let (palette, _) = alpha::store(&palette, color_depth, policy);
+        let stored = Self::from_indexed(Indexed {
+            palette: Palette(palette.into_raw_vec()),
+            indexes: indexed.indexes.clone(),
+        });
+        let report = alpha::report(&self.0, &stored.0, color_depth, policy);
+        (stored, report)
     }
 
-    pub fn to_palette_and_palette_texture(&self, tf: TextureFormat) -> (Palette, PaletteTexture) {
+    /// split into a palette and indexes, the texture must fit in the palette.
+    /// indexed textures keep their palette and indexes, other textures get a palette
+    /// in the order colors are first seen
+    pub fn to_palette_and_palette_texture(
+        &self,
+        tf: TextureFormat,
+    ) -> Result<(Palette, PaletteTexture)> {
         let max_len = 2usize.pow(tf.pixel_encoding as _);
+        if let Some(indexed) = &self.1 {
+            if indexed.palette.0.len() > max_len {
+                return Err(Error::TooManyColors {
+                    offset: tf.location as _,
+                    index: None,
+                    colors: indexed.palette.0.len(),
+                    max: max_len,
+                });
+            }
+            let mut palette = indexed.palette.clone();
+            palette.0.resize(max_len, Color::default());
+            return Ok((palette, indexed.indexes.clone()));
+        }
         let mut palette = Vec::with_capacity(max_len);
         let mut palette_map = HashMap::with_capacity(256);
         let mut palette_tex = Array2::default((self.0.nrows(), self.0.ncols()));
@@ -188,26 +857,70 @@ impl Texture {
         for (tex_c, tex_i) in self.0.iter().zip(palette_tex.iter_mut()) {
             // set index in palette texture
             *tex_i = *palette_map.entry(tex_c).or_insert_with(|| {
-                let i = palette.len();
-                if i == max_len {
-                    eprintln!("Error: Too many colors");
-                    std::process::exit(1);
-                }
                 palette.push(*tex_c);
-                i as _
+                palette.len() - 1
+            }) as _;
+        }
+        if palette.len() > max_len {
+            return Err(Error::TooManyColors {
+                offset: tf.location as _,
+                index: None,
+                colors: palette.len(),
+                max: max_len,
             });
         }
         palette.resize(max_len, Color::default());
-        (Palette(palette), PaletteTexture(palette_tex))
+        Ok((Palette(palette), PaletteTexture(palette_tex)))
     }
 }
 
+/// 8 bits per channel
 #[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
 pub struct Color {
+    /// red
     pub r: u8,
+    /// green
     pub g: u8,
+    /// blue
     pub b: u8,
+    /// alpha, 0xFF is opaque
     pub a: u8,
 }
 
-pub const CHUNK_SIZE: usize = 32;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    // a different color for every pixel, with partly transparent alpha
+    fn gradient() -> Texture {
+        Texture(
+            Array2::from_shape_fn((32, 32), |(y, x)| Color {
+                r: (x * 8) as u8,
+                g: (y * 8) as u8,
+                b: ((x + y) * 4) as u8,
+                a: ((x + y) * 4 + 3) as u8,
+            }),
+            None,
+        )
+    }
+
+    #[test]
+    fn quantizing_keeps_stored_alpha() {
+        for (color_depth, policy) in [
+            (16, AlphaPolicy::Dither),
+            (16, AlphaPolicy::default()),
+            (32, AlphaPolicy::default()),
+        ] {
+            let (stored, _) = gradient().store_alpha(color_depth, policy);
+            for dither in [Dither::None, Dither::FloydSteinberg, Dither::Ordered] {
+                let (quantized, _) = stored.quantize_stored(16, dither, color_depth, policy);
+                let colors = quantized.0.iter().collect::<HashSet<_>>();
+                assert!(colors.len() <= 16);
+                let (restored, report) = quantized.store_alpha(color_depth, policy);
+                assert_eq!(report.changed, 0, "{:?} {:?}", policy, dither);
+                assert_eq!(restored.0, quantized.0);
+            }
+        }
+    }
+}
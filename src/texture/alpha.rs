@@ -0,0 +1,131 @@
+use crate::texture::Color;
+use ndarray::Array2;
+use std::fmt;
+
+/// what happens to partly transparent pixels in 16-bit palettes,
+/// which only have 1 bit of alpha
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlphaPolicy {
+    /// opaque if alpha is at least this, transparent otherwise
+    Threshold(u8),
+    /// 4x4 bayer matrix, so partly transparent areas keep roughly the same coverage
+    Dither,
+    /// fail instead of changing alpha
+    Reject,
+}
+
+impl Default for AlphaPolicy {
+    fn default() -> Self {
+        Self::Threshold(0x80)
+    }
+}
+
+/// how the alpha of a replacement texture was changed to fit its palette
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AlphaReport {
+    /// bits of the palette colors, 16 or 32
+    pub color_depth: u8,
+    /// what was done to partly transparent pixels of 16-bit palettes
+    pub policy: AlphaPolicy,
+    /// pixels whose alpha was changed
+    pub changed: usize,
+    /// the biggest change, 0-255
+    pub max: u8,
+}
+
+impl fmt::Display for AlphaReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-bit palette, ", self.color_depth)?;
+        if self.changed == 0 {
+            return write!(f, "alpha kept exactly");
+        }
+        match (self.color_depth, self.policy) {
+            (16, AlphaPolicy::Threshold(threshold)) => write!(
+                f,
+                "alpha of {} partly transparent pixels thresholded at {}",
+                self.changed, threshold
+            )?,
+            (16, _) => write!(
+                f,
+                "alpha of {} partly transparent pixels dithered",
+                self.changed
+            )?,
+            _ => write!(f, "alpha of {} pixels rounded to 7 bits", self.changed)?,
+        }
+        write!(f, ", max change {}", self.max)
+    }
+}
+
+// 32-bit palettes store alpha as 0-127, where 127 is opaque.
+// integer rounding gives the same results as rounding floats, but works in a const
+pub(crate) const fn from_7bit(a: u8) -> u8 {
+    let a = (a as u32 * 255 + 63) / 127;
+    if a > 255 {
+        255
+    } else {
+        a as u8
+    }
+}
+
+pub(crate) const fn to_7bit(a: u8) -> u8 {
+    ((a as u32 * 127 + 127) / 255) as u8
+}
+
+// every alpha read from a 32-bit palette is written back the same
+const _: () = {
+    let mut a = 0;
+    while a <= 127 {
+        assert!(to_7bit(from_7bit(a)) == a);
+        a += 1;
+    }
+};
+
+/// change alpha to what the palette can store
+pub fn store(
+    texture: &Array2<Color>,
+    color_depth: u8,
+    policy: AlphaPolicy,
+) -> (Array2<Color>, AlphaReport) {
+    const BAYER: [[u32; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+    let stored = Array2::from_shape_fn(texture.dim(), |(y, x)| {
+        let color = texture[[y, x]];
+        let opaque = |threshold: u32| {
+            if color.a as u32 >= threshold {
+                0xFF
+            } else {
+                0x00
+            }
+        };
+        let a = match (color_depth, policy) {
+            (16, AlphaPolicy::Threshold(threshold)) => opaque(threshold as u32),
+            // thresholds from 7 to 247, so only partly transparent pixels are dithered
+            (16, AlphaPolicy::Dither) => opaque((BAYER[y % 4][x % 4] * 2 + 1) * 255 / 32),
+            (16, AlphaPolicy::Reject) => opaque(0x80),
+            _ => from_7bit(to_7bit(color.a)),
+        };
+        Color { a, ..color }
+    });
+    let report = report(texture, &stored, color_depth, policy);
+    (stored, report)
+}
+
+/// how the alpha of texture was changed into the alpha of stored
+pub fn report(
+    texture: &Array2<Color>,
+    stored: &Array2<Color>,
+    color_depth: u8,
+    policy: AlphaPolicy,
+) -> AlphaReport {
+    let mut report = AlphaReport {
+        color_depth,
+        policy,
+        ..Default::default()
+    };
+    for (color, new) in texture.iter().zip(stored.iter()) {
+        if color.a != new.a {
+            report.changed += 1;
+            report.max = report.max.max(color.a.abs_diff(new.a));
+        }
+    }
+    report
+}
@@ -1,182 +1,266 @@
-use crate::texture::CHUNK_SIZE;
-use num_traits::PrimInt;
+use ndarray::{s, Array2};
+use std::collections::HashMap;
 
-// uses original C++ decoding,
-// I have not found a solution myself yet.
+// The game uploads 4-bit textures to the GS as 32-bit data (PSMCT32) that is
+// half as wide and a quarter as tall, and then samples that memory as 4-bit
+// (PSMT4). The two formats lay out pages, blocks and columns differently, so
+// pixels end up wherever the layouts disagree. Instead of undoing that one
+// shuffle at a time, store each pixel the 32-bit way and read it back the
+// 4-bit way.
 
-pub fn convert32x32<N: PrimInt>(array: &mut [N]) {
-    // Swap every other pair of columns
-    // (I read nibbles opposite of the C++ program)
-    // (no I won't just read nibbles in the same way)
-    for i in (0..array.len()).step_by(2) {
-        array.swap(i, i + 1);
-    }
+/// unscramble 4-bit image data of any size
+pub fn decode<T: Default + Copy>(array: &Array2<T>) -> Array2<T> {
+    convert(array, false)
+}
+
+/// scramble 4-bit image data of any size
+pub fn encode<T: Default + Copy>(array: &Array2<T>) -> Array2<T> {
+    convert(array, true)
+}
 
-    let mut temp_array = [N::zero(); 32 * 32];
+fn convert<T: Default + Copy>(array: &Array2<T>, encode: bool) -> Array2<T> {
+    let (height, width) = array.dim();
 
-    // Rearrange 16 sets of 64 contiguous pixels
-    // Original order is 0 8 1 9 2 10 3 11 4 12 5 13 6 14 7 15
-    // Needs to be rearranged to 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15
-    for i in 0..16 {
-        for j in 0..64 {
-            let index = if i % 2 == 0 {
-                i / 2 * 64 + j
+    if let Some(indexes) = swizzled_indexes(width, height) {
+        let from = array.iter().copied().collect::<Vec<_>>();
+        let mut to = vec![T::default(); from.len()];
+        for (i, index) in indexes.into_iter().enumerate() {
+            if encode {
+                to[index] = from[i];
             } else {
-                i / 2 * 64 + j + 32 * 16
-            };
-            temp_array[index] = array[i * 64 + j];
+                to[i] = from[index];
+            }
         }
+        return Array2::from_shape_vec(array.dim(), to).unwrap();
     }
 
-    // Load temp_array back into array1
-    for i in 0..32 * 32 {
-        array[i] = temp_array[i];
+    // not one rectangle in GS memory, it is made out of squares instead
+    // (e.g. 32x64 is two 32x32 textures on top of each other)
+    let size = width.min(height);
+    if size == width.max(height)
+        || !(width * height).is_multiple_of(size * size)
+        || swizzled_indexes(size, size).is_none()
+    {
+        // smaller than 32x32 (e.g. 16x16), or an odd shape. uploaded as 32-bit pixels it
+        // would fill part of a block, but the GS reads its 4-bit pixels from all over the
+        // block, so it can not be drawn from a 32-bit upload. it was uploaded as 4-bit
+        // pixels instead, which are stored in order
+        return array.clone();
     }
-
-    // Swap every other pair of pixels with the pair of pixels in the following row
-    for i in 0..16 {
-        for j in 0..32 {
-            if (i / 2 % 2 == 0 && (j % 4 == 1 || j % 4 == 2))
-                || (i / 2 % 2 == 1 && (j % 4 == 0 || j % 4 == 3))
-            {
-                let index1 = (i * 2 + 1) * 32 + j;
-                let index2 = i * 2 * 32 + j;
-                array.swap(index1, index2);
+    let mut out = Array2::default(array.dim());
+    if height > width {
+        for y in (0..height).step_by(size) {
+            let chunk = convert(&array.slice(s![y..y + size, ..]).to_owned(), encode);
+            out.slice_mut(s![y..y + size, ..]).assign(&chunk);
+        }
+    } else {
+        // each square is still stored one after the other
+        let from = array.iter().copied().collect::<Vec<_>>();
+        let mut to = Vec::with_capacity(from.len());
+        for (i, x) in (0..width).step_by(size).enumerate() {
+            if encode {
+                let chunk = convert(&array.slice(s![.., x..x + size]).to_owned(), encode);
+                to.extend(chunk.iter());
+            } else {
+                let chunk = from[i * size * size..(i + 1) * size * size].to_vec();
+                let chunk = Array2::from_shape_vec((size, size), chunk).unwrap();
+                out.slice_mut(s![.., x..x + size])
+                    .assign(&convert(&chunk, encode));
             }
         }
+        if encode {
+            out = Array2::from_shape_vec(array.dim(), to).unwrap();
+        }
     }
+    out
+}
 
-    // Put every 8th pixel in a 32-pixel row next to each other
-    for i in 0..32 {
-        for j in 0..32 {
-            temp_array[i * 32 + j] = array[i * 32 + (j % 4) * 8 + j / 4];
-        }
+// for every pixel of a decoded width x height 4-bit texture, find the index it
+// is stored at in the encoded texture.
+// returns None if the texture does not fill the 32-bit memory it was uploaded
+// to exactly (too small, or an odd shape), since then it was not swizzled.
+fn swizzled_indexes(width: usize, height: usize) -> Option<Vec<usize>> {
+    if !width.is_multiple_of(2) || !height.is_multiple_of(4) {
+        return None;
     }
 
-    for i in 0..32 * 32 {
-        array[i] = temp_array[i];
+    // 32-bit upload, pages are 64x32 pixels, 8 nibbles per pixel
+    let (width_32, height_32) = (width / 2, height / 4);
+    let pages_per_row_32 = width_32.div_ceil(64);
+    // 4-bit texture, pages are 128x128 pixels
+    let pages_per_row_4 = width.div_ceil(128);
+    if pages_per_row_32 != pages_per_row_4 {
+        return None;
     }
 
-    // "Unweave" adjacent 4-pixel-wide columns
-    for i in 0..16 {
-        for j in 0..4 {
-            for k in 0..4 {
-                let index1 = (i * 2 + 1) * 32 + j * 8 + 4 + k;
-                let index2 = i * 2 * 32 + j * 8 + k;
-                array.swap(index1, index2);
+    // store the index of each nibble at its address
+    let len = width * height;
+    let mut memory = HashMap::with_capacity(len);
+    for y in 0..height_32 {
+        for x in 0..width_32 {
+            let address = address_32(x, y, pages_per_row_32);
+            for nibble in 0..8 {
+                memory.insert(address * 8 + nibble, (y * width_32 + x) * 8 + nibble);
             }
         }
     }
 
-    // Switch odd pairs of columns
-    for i in 0..32 {
-        for j in 0..4 {
-            for k in 0..4 {
-                if j % 2 == 1 {
-                    let index1 = i * 32 + j * 8 + 4 + k;
-                    let index2 = i * 32 + j * 8 + k;
-                    array.swap(index1, index2);
-                }
+    // read the index of each nibble back from its address
+    let mut indexes = Vec::with_capacity(len);
+    let mut found = vec![false; len];
+    for y in 0..height {
+        for x in 0..width {
+            let index = *memory.get(&address_4(x, y, pages_per_row_4))?;
+            if found[index] {
+                return None;
             }
+            found[index] = true;
+            indexes.push(index);
         }
     }
+    Some(indexes)
+}
 
-    //one more pass to swap every other pair of rows
-    for i in 0..8 {
-        for j in 0..32 {
-            let index1 = i * 128 + 32 + j;
-            let index2 = i * 128 + 64 + j;
-            array.swap(index1, index2);
-        }
-    }
+// address of a 32-bit pixel in words
+fn address_32(x: usize, y: usize, pages_per_row: usize) -> usize {
+    let page = y / 32 * pages_per_row + x / 64;
+
+    // 8x8 pixel blocks, in page order:
+    //  0  1  4  5 16 17 20 21
+    //  2  3  6  7 18 19 22 23
+    //  8  9 12 13 24 25 28 29
+    // 10 11 14 15 26 27 30 31
+    let (bx, by) = (x / 8 % 8, y / 8 % 4);
+    let block = bx % 2 + by % 2 * 2 + bx / 2 % 2 * 4 + by / 2 * 8 + bx / 4 * 16;
+
+    // pixels in the block, in block order:
+    //  0  1  4  5  8  9 12 13
+    //  2  3  6  7 10 11 14 15
+    // 16 17 20 21 24 25 28 29
+    // ...
+    let (px, py) = (x % 8, y % 8);
+    let pixel = px % 2 + px / 2 * 4 + py % 2 * 2 + py / 2 * 16;
+
+    page * 2048 + block * 64 + pixel
 }
 
-pub fn convert64x64_4bit<N: PrimInt>(array: &mut [N]) {
-    // Swap every other pair of columns
-    // (I read nibbles opposite of the C++ program)
-    // (no I won't just read nibbles in the same way)
-    for i in (0..array.len()).step_by(2) {
-        array.swap(i, i + 1);
-    }
-
-    const HEIGHT: usize = 64;
-    const WIDTH: usize = 64;
-    for row in 0..HEIGHT {
-        if row / 8 % 2 == 1 {
-            for j in 0..WIDTH / 4 {
-                let temp = array[row * WIDTH + j * 2 + 1];
-                array[row * WIDTH + j * 2 + 1] = array[row * WIDTH + WIDTH / 2 + j * 2];
-                array[row * WIDTH + WIDTH / 2 + j * 2] =
-                    array[row * WIDTH + WIDTH / 2 + j * 2 + 1];
-                array[row * WIDTH + WIDTH / 2 + j * 2 + 1] = temp;
-            }
-        } else {
-            for j in 0..WIDTH / 4 {
-                let temp = array[row * WIDTH + WIDTH / 2 + j * 2];
-                array[row * WIDTH + WIDTH / 2 + j * 2] = array[row * WIDTH + j * 2 + 1];
-                array[row * WIDTH + j * 2 + 1] = array[row * WIDTH + j * 2];
-                array[row * WIDTH + j * 2] = temp;
-            }
+// address of a 4-bit pixel in nibbles
+fn address_4(x: usize, y: usize, pages_per_row: usize) -> usize {
+    let page = y / 128 * pages_per_row + x / 128;
+
+    // 32x16 pixel blocks, in page order:
+    //  0  2  8 10
+    //  1  3  9 11
+    //  4  6 12 14
+    //  5  7 13 15
+    // 16 18 24 26
+    // ...
+    let (bx, by) = (x / 32 % 4, y / 16 % 8);
+    let block = by % 2 + bx % 2 * 2 + by / 2 % 2 * 4 + bx / 2 * 8 + by / 4 * 16;
+
+    // pixels in the block come in 4 columns of 32x4 pixels.
+    // every 8 pixels of a row are spread out over the column,
+    // and every other pair of rows has its halves swapped
+    let (px, py) = (x % 32, y % 16);
+    let (column, row) = (py / 4, py % 4);
+    let group = px / 8;
+    let i = px % 8;
+    let swap = (i / 4) ^ (row / 2) ^ (column % 2);
+    let pixel =
+        column * 128 + i % 2 * 8 + i / 2 % 2 * 32 + swap * 64 + row % 2 * 16 + row / 2 + group * 2;
+
+    page * 16384 + block * 512 + pixel
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GenericImageView;
+    use std::collections::HashSet;
+
+    // the pixels of a reference image from research/resources
+    fn load(name: &str) -> Array2<[u8; 4]> {
+        let path = format!(
+            "{}/research/resources/{}.png",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        let img = image::open(path).unwrap();
+        let (width, height) = img.dimensions();
+        let mut pixels = Array2::default((height as usize, width as usize));
+        for (x, y, pixel) in img.pixels() {
+            pixels[[y as usize, x as usize]] = pixel.0;
         }
+        pixels
     }
-    let mut chunk_array = vec![N::zero(); array.len()];
-    for w in 0..WIDTH / CHUNK_SIZE {
-        for h in 0..HEIGHT / CHUNK_SIZE {
-            for c in 0..CHUNK_SIZE {
-                for d in 0..CHUNK_SIZE {
-                    chunk_array[c * CHUNK_SIZE + d] =
-                        array[h * WIDTH * CHUNK_SIZE + c * WIDTH + w * CHUNK_SIZE + d];
-                }
-            }
-            convert_4bit(&mut chunk_array);
-            for c in 0..CHUNK_SIZE {
-                for d in 0..CHUNK_SIZE {
-                    array[h * WIDTH * CHUNK_SIZE + c * WIDTH + w * CHUNK_SIZE + d] =
-                        chunk_array[c * CHUNK_SIZE + d];
-                }
-            }
-        }
+
+    // b has every pixel of a, moved around. every pixel of the test images is a different color
+    fn is_permutation<T: Copy + Eq + std::hash::Hash>(a: &Array2<T>, b: &Array2<T>) -> bool {
+        let mut pixels = a.iter().copied().collect::<HashSet<_>>();
+        pixels.len() == a.len() && b.iter().all(|pixel| pixels.remove(pixel)) && pixels.is_empty()
     }
-    let mut new_array = vec![N::zero(); array.len()];
-    for i in 0..64 {
-        for j in 0..32 {
-            if i % 2 == 1 {
-                new_array[(i - 1) * 2 * 32 + j + 32] = array
-                    [i % 16 / 4 * 16 * 32 + i / 16 * 4 * 32 + (1 - i / 2 % 2) * 32 + 32 * 64 + j];
-                new_array[(i - 1) * 2 * 32 + j + 32 + 64] = array[i % 16 / 4 * 16 * 32
-                    + i / 16 * 4 * 32
-                    + (1 - i / 2 % 2) * 32
-                    + 32 * 64
-                    + j
-                    + 64];
-            } else {
-                new_array[i * 2 * 32 + j] =
-                    array[i % 16 / 4 * 16 * 32 + i / 16 * 4 * 32 + (1 - i / 2 % 2) * 32 + j];
-                new_array[i * 2 * 32 + j + 64] =
-                    array[i % 16 / 4 * 16 * 32 + i / 16 * 4 * 32 + (1 - i / 2 % 2) * 32 + j + 64];
-            }
-        }
+
+    fn identity(width: usize, height: usize) -> Array2<u32> {
+        Array2::from_shape_fn((height, width), |(y, x)| (y * width + x) as u32)
     }
-    array.copy_from_slice(&new_array);
-}
 
-// unscramble 4-bit image data
-fn convert_4bit<N: PrimInt>(array: &mut [N]) {
-    let mut temp_array = vec![N::zero(); array.len()];
-    // put every 8th pixel in a 32-pixel row next to each other
-    for i in 0..32 {
-        for j in 0..32 {
-            temp_array[i * 32 + j] = array[i * 32 + (j % 4) * 8 + j / 4];
-        }
+    #[test]
+    fn decodes_64x64() {
+        let encoded = load("4-bit_64x64_encoded");
+        assert_eq!(decode(&encoded), load("4-bit_64x64_decoded"));
     }
-    // interweave every other row
-    for i in 0..32 {
-        for j in 0..32 {
-            if i % 2 == 0 {
-                array[i * 32 + j] = temp_array[i / 8 * 8 * 32 + i % 8 / 2 * 32 + j];
-            } else {
-                array[i * 32 + j] = temp_array[i / 8 * 8 * 32 + i % 8 / 2 * 32 + 4 * 32 + j];
-            }
+
+    #[test]
+    fn decodes_32x32() {
+        let encoded = load("4-bit_32x32_encoded");
+        assert_eq!(decode(&encoded), load("4-bit_32x32_decoded"));
+    }
+
+    #[test]
+    fn encodes_64x64() {
+        let decoded = load("4-bit_64x64_decoded");
+        assert_eq!(encode(&decoded), load("4-bit_64x64_encoded"));
+    }
+
+    // 256x256_decode_4b to 4d are steps from the research that only move columns of 4 pixels
+    // within rows, they are not a decoded texture. 4a is the texture they started from
+    #[test]
+    fn round_trips_256x256() {
+        let encoded = load("256x256_decode_4a");
+        let decoded = decode(&encoded);
+        assert_ne!(decoded, encoded);
+        assert!(is_permutation(&encoded, &decoded));
+        assert_eq!(encode(&decoded), encoded);
+    }
+
+    #[test]
+    fn round_trips() {
+        for (width, height) in [
+            (32, 32),
+            (64, 64),
+            (128, 128),
+            (512, 512),
+            (32, 64),
+            (32, 128),
+            (64, 32),
+            (128, 32),
+            (128, 256),
+            (256, 128),
+        ] {
+            let indexes = identity(width, height);
+            let decoded = decode(&indexes);
+            assert!(is_permutation(&indexes, &decoded), "{}x{}", width, height);
+            assert_eq!(encode(&decoded), indexes, "{}x{}", width, height);
+            assert_eq!(decode(&encode(&indexes)), indexes, "{}x{}", width, height);
         }
     }
+
+    // a 16x16 upload of 32-bit pixels would not fill the memory the 4-bit pixels are read from
+    #[test]
+    fn does_not_swizzle_16x16() {
+        assert!(swizzled_indexes(16, 16).is_none());
+        let indexes = identity(16, 16);
+        assert_eq!(decode(&indexes), indexes);
+        assert_eq!(encode(&indexes), indexes);
+    }
 }
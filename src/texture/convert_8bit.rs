@@ -1,7 +1,14 @@
 use ndarray::{s, Array2, ArrayView2, Axis};
 
+/// whether decode and encode can swizzle a texture of this size. they work on chunks of 32x4
+/// pixels and swap 4x4 chunks in groups of 8 rows, textures 8 pixels wide are not swizzled
+pub fn supports(width: usize, height: usize) -> bool {
+    width == 8 || (width >= 16 && height >= 8)
+}
+
 // there is probably a better way to do this, that does not involve swapping
 // middle quarters. I barely found this solution as it is though...
+/// unscramble 8-bit image data of a size supports says yes to
 pub fn decode<T: Default + Copy>(array: &Array2<T>) -> Array2<T> {
     let mut out = array.clone();
 
@@ -44,6 +51,7 @@ pub fn decode<T: Default + Copy>(array: &Array2<T>) -> Array2<T> {
     swap_4x4_chunks(&out)
 }
 
+/// scramble 8-bit image data of a size supports says yes to
 pub fn encode<T: Default + Copy>(array: &Array2<T>) -> Array2<T> {
     let mut out = array.clone();
 
@@ -86,7 +94,7 @@ pub fn encode<T: Default + Copy>(array: &Array2<T>) -> Array2<T> {
     out
 }
 
-pub fn swap_4x4_chunks<T: Default + Copy>(array: &Array2<T>) -> Array2<T> {
+fn swap_4x4_chunks<T: Default + Copy>(array: &Array2<T>) -> Array2<T> {
     let mut out = array.clone();
     for y in (2..array.nrows()).step_by(8) {
         for y in y..y + 4 {
@@ -101,7 +109,7 @@ pub fn swap_4x4_chunks<T: Default + Copy>(array: &Array2<T>) -> Array2<T> {
     out
 }
 
-pub fn swap_middle_quarters<T: Default + Copy>(array: &Array2<T>, columns: usize) -> Array2<T> {
+fn swap_middle_quarters<T: Default + Copy>(array: &Array2<T>, columns: usize) -> Array2<T> {
     let quarter = columns / 4;
     let mut out = array.clone();
     for y in 0..array.nrows() {
@@ -115,39 +123,33 @@ pub fn swap_middle_quarters<T: Default + Copy>(array: &Array2<T>, columns: usize
     out
 }
 
-pub fn split_by_column_parity<T: Default + Copy>(array: ArrayView2<T>) -> (Array2<T>, Array2<T>) {
+fn split_by_column_parity<T: Default + Copy>(array: ArrayView2<T>) -> (Array2<T>, Array2<T>) {
     let even = array.slice(s![.., ..;2]).to_owned();
     let odd = array.slice(s![.., 1..;2]).to_owned();
     (even, odd)
 }
 
-pub fn join_by_column_parity<T: Default + Copy>(
-    even: ArrayView2<T>,
-    odd: ArrayView2<T>,
-) -> Array2<T> {
+fn join_by_column_parity<T: Default + Copy>(even: ArrayView2<T>, odd: ArrayView2<T>) -> Array2<T> {
     let mut out = Array2::default((even.nrows(), even.ncols() + odd.ncols()));
     out.slice_mut(s![.., ..;2]).assign(&even);
     out.slice_mut(s![.., 1..;2]).assign(&odd);
     out
 }
 
-pub fn split_by_row_parity<T: Default + Copy>(array: ArrayView2<T>) -> (Array2<T>, Array2<T>) {
+fn split_by_row_parity<T: Default + Copy>(array: ArrayView2<T>) -> (Array2<T>, Array2<T>) {
     let even = array.slice(s![..;2, ..]).to_owned();
     let odd = array.slice(s![1..;2, ..]).to_owned();
     (even, odd)
 }
 
-pub fn join_by_row_parity<T: Default + Copy>(
-    even: ArrayView2<T>,
-    odd: ArrayView2<T>,
-) -> Array2<T> {
+fn join_by_row_parity<T: Default + Copy>(even: ArrayView2<T>, odd: ArrayView2<T>) -> Array2<T> {
     let mut out = Array2::default((even.nrows() + odd.nrows(), even.ncols()));
     out.slice_mut(s![..;2, ..]).assign(&even);
     out.slice_mut(s![1..;2, ..]).assign(&odd);
     out
 }
 
-pub fn join_horizontally<'a, T: Default + Copy>(
+fn join_horizontally<'a, T: Default + Copy>(
     lhs: ArrayView2<'a, T>,
     rhs: ArrayView2<'a, T>,
 ) -> Array2<T> {
@@ -155,13 +157,11 @@ pub fn join_horizontally<'a, T: Default + Copy>(
 }
 
 /// returns (lhs, rhs)
-pub fn split_vertically<T: Default + Copy>(
-    array: ArrayView2<T>,
-) -> (ArrayView2<T>, ArrayView2<T>) {
+fn split_vertically<T: Default + Copy>(array: ArrayView2<T>) -> (ArrayView2<T>, ArrayView2<T>) {
     array.split_at(Axis(1), array.ncols() / 2)
 }
 
-pub fn join_vertically<'a, T: Default + Copy>(
+fn join_vertically<'a, T: Default + Copy>(
     top: ArrayView2<'a, T>,
     bottom: ArrayView2<'a, T>,
 ) -> Array2<T> {
@@ -169,8 +169,6 @@ pub fn join_vertically<'a, T: Default + Copy>(
 }
 
 /// returns (top, bottom)
-pub fn split_horizontally<T: Default + Copy>(
-    array: ArrayView2<T>,
-) -> (ArrayView2<T>, ArrayView2<T>) {
+fn split_horizontally<T: Default + Copy>(array: ArrayView2<T>) -> (ArrayView2<T>, ArrayView2<T>) {
     array.split_at(Axis(0), array.nrows() / 2)
 }
@@ -0,0 +1,124 @@
+use crate::{
+    error::Result,
+    texture::{palette::Palette, palette_texture::PaletteTexture, Color},
+};
+use itertools::Itertools;
+use ndarray::{s, Array2};
+use png::{BitDepth, ColorType};
+use std::io::{self, BufWriter, Cursor};
+use std::path::Path;
+
+/// palette indexes, and the palette they index, in the order the game stores them
+#[derive(Clone)]
+pub struct Indexed {
+    /// every color, including unused ones
+    pub palette: Palette,
+    /// index into the palette of each pixel
+    pub indexes: PaletteTexture,
+}
+
+impl Indexed {
+    /// decoded pixels, by row
+    pub fn pixels(&self) -> Array2<Color> {
+        self.indexes.0.map(|i| self.palette.0[*i as usize])
+    }
+
+    /// write as a png with the palette as PLTE and tRNS, 4 bits per pixel for 16 colors,
+    /// 8 bits otherwise. image should be flipped, unless you are testing something
+    pub fn write_to_image(&self, path: &Path, flip: bool) -> Result<()> {
+        let (height, width) = self.indexes.0.dim();
+        let (depth, bits) = match self.palette.0.len() {
+            ..=16 => (BitDepth::Four, 4),
+            _ => (BitDepth::Eight, 8),
+        };
+        let mut encoder = png::Encoder::new(
+            BufWriter::new(std::fs::File::create(path)?),
+            width as u32,
+            height as u32,
+        );
+        encoder.set_color(ColorType::Indexed);
+        encoder.set_depth(depth);
+        encoder.set_palette(
+            self.palette
+                .0
+                .iter()
+                .flat_map(|c| [c.r, c.g, c.b])
+                .collect::<Vec<_>>(),
+        );
+        encoder.set_trns(self.palette.0.iter().map(|c| c.a).collect::<Vec<_>>());
+
+        // rows are packed from the high bits, and start on a new byte
+        let indexes = match flip {
+            true => self.indexes.0.slice(s![..;-1, ..]),
+            false => self.indexes.0.view(),
+        };
+        let per_byte = 8 / bits;
+        let mut data = Vec::with_capacity(height * width.div_ceil(per_byte));
+        for row in indexes.rows() {
+            for chunk in &row.iter().chunks(per_byte) {
+                let byte = chunk.enumerate().fold(0, |byte, (i, index)| {
+                    byte | (*index as u8) << (8 - bits * (i + 1))
+                });
+                data.push(byte);
+            }
+        }
+        encoder.write_header()?.write_image_data(&data)?;
+        Ok(())
+    }
+
+    /// the indexes and palette of an indexed png, none if the file is not one.
+    /// colors without tRNS alpha are opaque.
+    /// image should be flipped, unless you are testing something
+    pub fn read_from_image(path: &Path, flip: bool) -> Result<Option<Self>> {
+        let file = std::fs::read(path)?;
+        if !file.starts_with(b"\x89PNG") {
+            return Ok(None);
+        }
+        let mut reader = png::Decoder::new(Cursor::new(file)).read_info()?;
+        let info = reader.info();
+        if info.color_type != ColorType::Indexed {
+            return Ok(None);
+        }
+        let bits = info.bit_depth as usize;
+        let palette = info.palette.as_deref().unwrap_or_default();
+        let trns = info.trns.as_deref().unwrap_or_default();
+        let palette = palette
+            .chunks_exact(3)
+            .enumerate()
+            .map(|(i, rgb)| Color {
+                r: rgb[0],
+                g: rgb[1],
+                b: rgb[2],
+                a: trns.get(i).copied().unwrap_or(0xFF),
+            })
+            .collect::<Vec<_>>();
+
+        let mut data = vec![0; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut data)?;
+        let (width, height) = (frame.width as usize, frame.height as usize);
+        let per_byte = 8 / bits;
+        let mask = (1 << bits) - 1;
+        let indexes = Array2::from_shape_fn((height, width), |(y, x)| {
+            let y = if flip { height - 1 - y } else { y };
+            let byte = data[y * frame.line_size + x / per_byte];
+            (byte as u16 >> (8 - bits * (x % per_byte + 1))) & mask
+        });
+
+        if let Some(index) = indexes.iter().find(|i| **i as usize >= palette.len()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{}: palette index {} is past the {} colors of the palette",
+                    path.display(),
+                    index,
+                    palette.len()
+                ),
+            )
+            .into());
+        }
+        Ok(Some(Self {
+            palette: Palette(palette),
+            indexes: PaletteTexture(indexes),
+        }))
+    }
+}
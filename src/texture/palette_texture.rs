@@ -1,20 +1,22 @@
-use crate::texture::convert_8bit::swap_4x4_chunks;
-use crate::texture::{convert_4bit, convert_8bit, texture_format::TextureFormat, CHUNK_SIZE};
+use crate::{
+    error::Result,
+    texture::{convert_4bit, convert_8bit, texture_format::TextureFormat},
+};
 use byteorder::*;
-use ndarray::{s, Array2};
-use std::fs::{self, File};
-use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
-use std::path::Path;
-use std::process::exit;
+use itertools::Itertools;
+use ndarray::Array2;
+use std::io::{Read, Seek, SeekFrom, Write};
 
-// texture before applying palette
+/// texture before applying palette, palette indexes by row
+#[derive(Clone)]
 pub struct PaletteTexture(
     // u16 used for testing
     pub Array2<u16>,
 );
 
 impl PaletteTexture {
-    pub fn read_from_bin<T: Read + Seek>(reader: &mut T, tf: TextureFormat) -> io::Result<Self> {
+    /// read and unscramble the palette indexes of a texture format
+    pub fn read_from_bin<T: Read + Seek>(reader: &mut T, tf: TextureFormat) -> Result<Self> {
         // read palette indexes texture
         reader.seek(SeekFrom::Start((tf.location) as _))?;
         let mut palette_tex: Array2<u16> = Array2::default((tf.size.y as _, tf.size.x as _));
@@ -34,57 +36,50 @@ impl PaletteTexture {
                     });
                     *value = palette_index as _;
                 }
-                _ => unreachable!(),
+                _ => return Err(tf.unsupported_encoding()),
             }
         }
 
-        // decode
+        // decode, textures uploaded as indexed pixels are stored in order
         match tf.pixel_encoding {
+            _ if !tf.double_size => (),
             8 => {
                 palette_tex = convert_8bit::decode(&palette_tex);
             }
             4 => {
-                // if width is 64, decode blah blah blah
-                if tf.size.x == 64 && tf.size.y == 64 {
-                    convert_4bit::convert64x64_4bit(palette_tex.as_slice_mut().unwrap());
-                }
-                // if width is 32, each 32x32 pixel chunk needs decoded
-                if tf.size.x == 32 && tf.size.y >= 32 {
-                    for h in (0..tf.size.y as usize).step_by(32) {
-                        convert_4bit::convert32x32(
-                            palette_tex
-                                .slice_mut(s![h..h + 32, ..])
-                                .as_slice_mut()
-                                .unwrap(),
-                        );
-                    }
-                }
+                palette_tex = convert_4bit::decode(&palette_tex);
             }
-            _ => unreachable!(),
+            _ => return Err(tf.unsupported_encoding()),
         }
 
         Ok(Self(palette_tex))
     }
 
-    pub fn write_to_bin<T: Write + Seek>(
-        &self,
-        writer: &mut T,
-        tf: TextureFormat,
-    ) -> io::Result<()> {
-        // encode
+    /// scramble and write the palette indexes of a texture format
+    pub fn write_to_bin<T: Write + Seek>(&self, writer: &mut T, tf: TextureFormat) -> Result<()> {
+        // encode, textures uploaded as indexed pixels are stored in order
         let encoded = match tf.pixel_encoding {
+            _ if !tf.double_size => self.0.clone(),
             8 => convert_8bit::encode(&self.0),
-            4 => {
-                eprintln!("Error: Writing 4-bit images is currently unsupported");
-                std::process::exit(1)
-            }
-            _ => unreachable!(),
+            4 => convert_4bit::encode(&self.0),
+            _ => return Err(tf.unsupported_encoding()),
         };
 
         // write palette indexes texture
         writer.seek(SeekFrom::Start((tf.location) as _))?;
-        for value in encoded.iter() {
-            writer.write_u8(*value as _)?;
+        match tf.pixel_encoding {
+            8 => {
+                for value in encoded.iter() {
+                    writer.write_u8(*value as _)?;
+                }
+            }
+            4 => {
+                // first pixel goes in the low nibble, second in the high nibble
+                for (low, high) in encoded.iter().tuples() {
+                    writer.write_u8((*low as u8 & 0x0F) | ((*high as u8 & 0x0F) << 4))?;
+                }
+            }
+            _ => return Err(tf.unsupported_encoding()),
         }
 
         Ok(())
@@ -0,0 +1,272 @@
+use crate::texture::Color;
+use ndarray::Array2;
+use std::collections::HashMap;
+
+/// how far the quantized texture is from the original,
+/// as the distance between colors in RGBA space (0-255 per channel)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ColorError {
+    /// colors of the original
+    pub colors: usize,
+    /// mean distance of each pixel
+    pub mean: f32,
+    /// largest distance of a pixel
+    pub max: f32,
+}
+
+/// how colors that are lost when quantizing are spread
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dither {
+    /// each pixel gets the closest color
+    None,
+    /// the error of each pixel is spread to the pixels after it
+    FloydSteinberg,
+    /// 4x4 bayer matrix
+    Ordered,
+}
+
+/// reduce the texture to at most max_len colors using median cut
+pub fn quantize(
+    texture: &Array2<Color>,
+    max_len: usize,
+    dither: Dither,
+) -> (Array2<Color>, ColorError) {
+    // count each color
+    let mut counts = HashMap::<Color, usize>::new();
+    for color in texture.iter() {
+        *counts.entry(*color).or_default() += 1;
+    }
+    let mut error = ColorError {
+        colors: counts.len(),
+        ..Default::default()
+    };
+    if counts.len() <= max_len {
+        return (texture.clone(), error);
+    }
+
+    let palette = median_cut(counts.into_iter().collect(), max_len);
+
+    // replace each color with the closest color in the palette
+    let quantized = match dither {
+        Dither::None => {
+            let mut closest = HashMap::<Color, Color>::new();
+            texture.map(|color| {
+                *closest
+                    .entry(*color)
+                    .or_insert_with(|| nearest(&palette, color))
+            })
+        }
+        Dither::FloydSteinberg => floyd_steinberg(texture, &palette),
+        Dither::Ordered => ordered(texture, &palette),
+    };
+
+    for (color, new) in texture.iter().zip(quantized.iter()) {
+        let distance = (distance_squared(color, new) as f32).sqrt();
+        error.mean += distance;
+        error.max = error.max.max(distance);
+    }
+    error.mean /= texture.len() as f32;
+
+    (quantized, error)
+}
+
+fn nearest(palette: &[Color], color: &Color) -> Color {
+    *palette
+        .iter()
+        .min_by_key(|p| distance_squared(color, p))
+        .unwrap()
+}
+
+// push the error of each pixel onto the pixels that have not been done yet.
+// only the color channels are dithered, speckled transparency looks worse
+// than banded transparency
+fn floyd_steinberg(texture: &Array2<Color>, palette: &[Color]) -> Array2<Color> {
+    let (height, width) = texture.dim();
+    let mut errors = Array2::<[f32; 3]>::default(texture.dim());
+    let mut out = Array2::default(texture.dim());
+    for y in 0..height {
+        for x in 0..width {
+            let color = texture[[y, x]];
+            let e = errors[[y, x]];
+            let wanted = Color {
+                r: (color.r as f32 + e[0]).round().clamp(0., 255.) as u8,
+                g: (color.g as f32 + e[1]).round().clamp(0., 255.) as u8,
+                b: (color.b as f32 + e[2]).round().clamp(0., 255.) as u8,
+                a: color.a,
+            };
+            let new = nearest(palette, &wanted);
+            out[[y, x]] = new;
+
+            let diff = [
+                color.r as f32 + e[0] - new.r as f32,
+                color.g as f32 + e[1] - new.g as f32,
+                color.b as f32 + e[2] - new.b as f32,
+            ];
+            let mut spread = |x: usize, y: usize, weight: f32| {
+                if x < width && y < height {
+                    for (e, diff) in errors[[y, x]].iter_mut().zip(diff) {
+                        *e += diff * weight;
+                    }
+                }
+            };
+            spread(x + 1, y, 7. / 16.);
+            if x > 0 {
+                spread(x - 1, y + 1, 3. / 16.);
+            }
+            spread(x, y + 1, 5. / 16.);
+            spread(x + 1, y + 1, 1. / 16.);
+        }
+    }
+    out
+}
+
+// nudge each pixel by a fixed pattern before finding the closest color
+fn ordered(texture: &Array2<Color>, palette: &[Color]) -> Array2<Color> {
+    const BAYER: [[f32; 4]; 4] = [
+        [0., 8., 2., 10.],
+        [12., 4., 14., 6.],
+        [3., 11., 1., 9.],
+        [15., 7., 13., 5.],
+    ];
+    // roughly the distance between palette colors
+    let step = 255. / (palette.len() as f32).cbrt();
+    Array2::from_shape_fn(texture.dim(), |(y, x)| {
+        let color = texture[[y, x]];
+        let offset = ((BAYER[y % 4][x % 4] + 0.5) / 16. - 0.5) * step;
+        let nudge = |c: u8| (c as f32 + offset).round().clamp(0., 255.) as u8;
+        let wanted = Color {
+            r: nudge(color.r),
+            g: nudge(color.g),
+            b: nudge(color.b),
+            a: color.a,
+        };
+        nearest(palette, &wanted)
+    })
+}
+
+fn distance_squared(a: &Color, b: &Color) -> u32 {
+    let channel = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    channel(a.r, b.r) + channel(a.g, b.g) + channel(a.b, b.b) + channel(a.a, b.a)
+}
+
+fn channel(color: &Color, channel: usize) -> u8 {
+    match channel {
+        0 => color.r,
+        1 => color.g,
+        2 => color.b,
+        3 => color.a,
+        _ => unreachable!(),
+    }
+}
+
+// split the colors into boxes, always cutting the box with the widest channel
+// at its median, then average each box into one palette color
+fn median_cut(colors: Vec<(Color, usize)>, max_len: usize) -> Vec<Color> {
+    let mut boxes = vec![colors];
+    while boxes.len() < max_len {
+        // find the box and channel with the biggest range
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .flat_map(|(i, b)| {
+                (0..4).map(move |c| {
+                    let (min, max) = b.iter().fold((u8::MAX, u8::MIN), |(min, max), (color, _)| {
+                        let value = channel(color, c);
+                        (min.min(value), max.max(value))
+                    });
+                    (i, c, max - min)
+                })
+            })
+            .max_by_key(|(_, _, range)| *range);
+        let Some((i, c, _)) = widest else {
+            // every box is a single color
+            break;
+        };
+
+        // cut at the median pixel, not the median color
+        let mut b = boxes.swap_remove(i);
+        b.sort_by_key(|(color, _)| channel(color, c));
+        let total: usize = b.iter().map(|(_, count)| count).sum();
+        let mut seen = 0;
+        let mut cut = 1;
+        for (j, (_, count)) in b.iter().enumerate() {
+            seen += count;
+            if seen * 2 >= total {
+                cut = (j + 1).clamp(1, b.len() - 1);
+                break;
+            }
+        }
+        let other = b.split_off(cut);
+        boxes.push(b);
+        boxes.push(other);
+    }
+
+    boxes
+        .iter()
+        .map(|b| {
+            let total: usize = b.iter().map(|(_, count)| count).sum();
+            let mean = |c| {
+                let sum: usize = b
+                    .iter()
+                    .map(|(color, count)| channel(color, c) as usize * count)
+                    .sum();
+                ((sum as f32) / (total as f32)).round() as u8
+            };
+            Color {
+                r: mean(0),
+                g: mean(1),
+                b: mean(2),
+                a: mean(3),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    const DITHERS: [Dither; 3] = [Dither::None, Dither::FloydSteinberg, Dither::Ordered];
+
+    // a different color for every pixel
+    fn gradient(width: usize, height: usize) -> Array2<Color> {
+        Array2::from_shape_fn((height, width), |(y, x)| Color {
+            r: (x * 255 / (width - 1)) as u8,
+            g: (y * 255 / (height - 1)) as u8,
+            b: ((x + y) * 255 / (width + height - 2)) as u8,
+            a: if x % 2 == 0 { 0xFF } else { 0x80 },
+        })
+    }
+
+    fn colors(texture: &Array2<Color>) -> usize {
+        texture.iter().collect::<HashSet<_>>().len()
+    }
+
+    #[test]
+    fn reduces_to_at_most_max_len_colors() {
+        let texture = gradient(32, 32);
+        for dither in DITHERS {
+            for max_len in [16, 256] {
+                let (quantized, error) = quantize(&texture, max_len, dither);
+                assert_eq!(quantized.dim(), texture.dim());
+                assert!(colors(&quantized) <= max_len, "{:?} {}", dither, max_len);
+                assert_eq!(error.colors, 1024);
+                assert!(error.mean > 0.0 && error.max >= error.mean);
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_textures_with_few_enough_colors() {
+        let texture = gradient(4, 4);
+        for dither in DITHERS {
+            let (quantized, error) = quantize(&texture, 16, dither);
+            assert_eq!(quantized, texture);
+            assert_eq!(error.colors, 16);
+            assert_eq!(error.mean, 0.0);
+            assert_eq!(error.max, 0.0);
+        }
+    }
+}
@@ -1,12 +1,88 @@
-use crate::vec::Vec2;
-use byteorder::*;
-use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
+use crate::{
+    error::{Error, Result},
+    texture::convert_8bit,
+    texture::texture_header::{
+        conversion, TextureHeader, Upload, BITBLTBUF, IMAGE, PALETTE, PSMT4, PSMT8, REF, TRXREG,
+    },
+    vec::Vec2,
+};
+use std::{
+    fmt,
+    io::{self, Cursor, ErrorKind, Read, Seek, SeekFrom, Write},
+    ops::Range,
+};
 
-pub struct TextureFormats(pub Vec<TextureFormat>);
+/// texture formats of a binary file, in the order the textures are stored,
+/// why the ones that were rejected were, and what else is worth knowing about them
+pub struct TextureFormats(pub Vec<TextureFormat>, pub Vec<Error>, pub Vec<Warning>);
+
+/// something about where the textures of a bank are that is worth knowing,
+/// but did not stop it from being read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Warning {
+    /// headers that do not say where their texture is, so it was estimated
+    /// from the texture before it
+    Estimated {
+        /// bytes, where the first header is
+        header: u32,
+        /// headers whose texture was estimated
+        estimated: usize,
+        /// headers that were kept
+        len: usize,
+    },
+    /// headers that say their texture is somewhere else than estimated
+    /// from the texture before it
+    Different {
+        /// bytes, where the first header is
+        header: u32,
+        /// headers whose texture is somewhere else
+        different: usize,
+        /// headers that were kept
+        len: usize,
+    },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Warning::Estimated {
+                header,
+                estimated,
+                len,
+            } => write!(
+                f,
+                "Texture formats at {:#x}: {} of {} headers do not say where their texture is, \
+                estimated from the texture before it instead",
+                header, estimated, len
+            ),
+            Warning::Different {
+                header,
+                different,
+                len,
+            } => write!(
+                f,
+                "Texture formats at {:#x}: {} of {} headers say their texture is somewhere \
+                else than estimated from the texture before it",
+                header, different, len
+            ),
+        }
+    }
+}
 
 impl TextureFormats {
-    pub fn read<T: Read + Seek>(reader: &mut T, verbose: bool) -> io::Result<Self> {
+    /// find and read the texture formats, UnexpectedEof if there are none.
+    /// texture formats that do not describe a texture this can read, that fits in the file
+    /// without overlapping anything else, are rejected, and so are the ones after them
+    /// that do not say where their texture is, since it would be estimated in the wrong place.
+    /// the texture formats that are kept are numbered in order
+    pub fn read<T: Read + Seek>(reader: &mut T) -> io::Result<Self> {
         let mut texture_formats = Vec::new();
+        let mut rejected = Vec::new();
+        // whether a texture format before each one was rejected
+        let mut after_rejected = Vec::new();
+        let position = reader.stream_position()?;
+        let file_len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(position))?;
 
         // discard until 06 00 00 10 00 00 00 00 00 00 00 00 00 00 00 00
         loop {
@@ -17,65 +93,26 @@ impl TextureFormats {
             let mut signature = [0; 16];
             let read = reader.read(&mut signature)?;
             if read == 0 {
-                println!("Texture EOF!");
                 return Err(ErrorKind::UnexpectedEof.into());
             }
             if signature == FIND_SIGNATURE {
                 break;
             }
         }
+        let first_header = reader.stream_position()? - 0x10;
 
         loop {
-            // skip
-            reader.seek(SeekFrom::Current(0x17))?;
-
-            // read double size flag
-            let double_size = reader.read_u8()? == 0;
-
-            // skip
-            reader.seek(SeekFrom::Current(0x18))?;
-
-            // read size
-            let mut size = Vec2 {
-                x: reader.read_u32::<LE>()?,
-                y: reader.read_u32::<LE>()?,
-            };
-            if size.x > 512 || size.y > 512 {
-                println!(
-                    "size [{}, {}] is way too big. I'm outta here!",
-                    size.x, size.y
-                );
-                break;
+            // the signature has just been read
+            let header = reader.stream_position()? as u32 - 0x10;
+            reader.seek(SeekFrom::Start(header as _))?;
+            let gs = TextureHeader::read(reader)?;
+            match TextureFormat::new(header, gs) {
+                Ok(tf) => {
+                    after_rejected.push(!rejected.is_empty());
+                    texture_formats.push(tf);
+                }
+                Err(e) => rejected.push(e),
             }
-            if double_size {
-                size = size * 2;
-            }
-
-            // skip
-            reader.seek(SeekFrom::Current(0x5F))?;
-
-            // read color depth (bits)
-            let color_depth = if reader.read_u8()? == 0 { 32 } else { 16 };
-
-            // skip
-            reader.seek(SeekFrom::Current(0x48))?;
-
-            // read pixel encoding (bits)
-            let pixel_encoding = if reader.read_u8()? == 2 { 4 } else { 8 };
-            // for some reason height might need double again?
-            if pixel_encoding == 4 && double_size {
-                size.y *= 2;
-            }
-
-            texture_formats.push(TextureFormat {
-                size,
-                color_depth,
-                pixel_encoding,
-                location: 0, // not set yet
-            });
-
-            // skip
-            reader.seek(SeekFrom::Current(0x0F))?;
 
             // read end
             const FIND_END: [u8; 16] = [
@@ -83,55 +120,505 @@ impl TextureFormats {
                 0x00, 0x00, 0x00,
             ];
             let mut end = [0; 16];
-            reader.read(&mut end)?;
+            reader.read_exact(&mut end)?;
             if end == FIND_END {
                 break;
             }
         }
 
-        let counter = reader.seek(SeekFrom::Current(0))? as u32;
+        let counter = reader.stream_position()? as u32;
 
         // align to 0x100
-        let mut tex_location = (0x100 - (counter % 0x100)) + counter;
+        let tex_location = (0x100 - (counter % 0x100)) + counter;
+
+        // estimate texture locations, each texture right after the previous one
+        Self::calculate_locations(&mut texture_formats, tex_location);
 
-        // calculate texture locations
+        // where the headers say the textures and palettes are,
+        // compared with the estimate when both are known
+        let mut estimated = 0;
+        let mut different = 0;
         for tf in texture_formats.iter_mut() {
-            tf.location = tex_location;
-            match tf.color_depth {
-                16 => match tf.pixel_encoding {
-                    4 => tex_location += tf.size.x * tf.size.y / 2 + 0x20,
-                    8 => tex_location += tf.size.x * tf.size.y + 0x200,
-                    _ => unreachable!(),
-                },
-                32 => tex_location += tf.size.x * tf.size.y + 0x400,
-                _ => unreachable!(),
+            let (texture_len, palette_len) = (tf.texture_len(), tf.palette_len());
+            match tf.gs.locations(texture_len, palette_len, file_len) {
+                Some((location, palette_location)) => {
+                    if (location, palette_location) != (tf.location, tf.palette_location) {
+                        different += 1;
+                    }
+                    tf.location = location;
+                    tf.palette_location = palette_location;
+                    tf.addressed = true;
+                }
+                None => estimated += 1,
+            }
+        }
+        let mut warnings = Vec::new();
+        if let Some(first) = texture_formats.first() {
+            if estimated > 0 {
+                warnings.push(Warning::Estimated {
+                    header: first.header,
+                    estimated,
+                    len: texture_formats.len(),
+                });
+            }
+            if different > 0 {
+                warnings.push(Warning::Different {
+                    header: first.header,
+                    different,
+                    len: texture_formats.len(),
+                });
             }
         }
 
-        // output information
-        if verbose {
-            println!("Texture formats:");
-            for (i, tf) in texture_formats.iter().enumerate() {
-                println!("- Texture format:");
-                println!(
-                    "    size: [{}, {}], color_depth: {}, pixel_encoding: {}, location: {:#x}, index: {}",
-                    tf.size.x, tf.size.y, tf.color_depth, tf.pixel_encoding, tf.location, i
-                );
+        // textures must be inside the file, and not overlap the texture formats or each other
+        let headers = first_header..counter as u64;
+        let mut taken = Vec::from([headers]);
+        let mut kept = Vec::with_capacity(texture_formats.len());
+        let mut range_rejected = false;
+        for (tf, after_rejected) in texture_formats.into_iter().zip(after_rejected) {
+            if !tf.addressed && (after_rejected || range_rejected) {
+                rejected.push(Error::AfterRejected {
+                    offset: tf.header as _,
+                    index: None,
+                });
+                continue;
+            }
+            let ranges = tf
+                .ranges()
+                .map(|range| range.start as u64..range.end as u64);
+            let out_of_file = ranges.iter().find(|range| range.end > file_len);
+            let overlapped = ranges.iter().find_map(|range| {
+                taken
+                    .iter()
+                    .find(|taken| range.start < taken.end && taken.start < range.end)
+                    .map(|taken| (range, taken))
+            });
+            let valid_range = if let Some(range) = out_of_file {
+                Err(Error::OutOfFile {
+                    offset: range.start,
+                    index: None,
+                    end: range.end,
+                    len: file_len,
+                })
+            } else if let Some((range, overlapped)) = overlapped {
+                Err(Error::Overlap {
+                    offset: range.start,
+                    index: None,
+                    other: overlapped.start,
+                })
+            } else {
+                Ok(())
+            };
+            match valid_range {
+                Ok(()) => {
+                    taken.extend(ranges);
+                    kept.push(tf);
+                }
+                Err(e) => {
+                    rejected.push(e);
+                    range_rejected = true;
+                }
             }
         }
+        let texture_formats = kept;
+
+        Ok(Self(texture_formats, rejected, warnings))
+    }
 
-        Ok(Self(texture_formats))
+    /// find and read every bank of texture formats, in the order they are stored.
+    /// each bank is searched for after the textures of the previous one.
+    /// texture formats rejected where no texture format was kept go with the next bank,
+    /// or the last one if there is no next bank
+    pub fn read_banks(data: &[u8]) -> io::Result<Vec<Self>> {
+        let mut reader = Cursor::new(data);
+        let mut banks = Vec::<Self>::new();
+        let mut rejected = Vec::new();
+        loop {
+            match Self::read(&mut reader) {
+                Ok(mut tfs) => {
+                    if tfs.0.is_empty() {
+                        rejected.append(&mut tfs.1);
+                        // keep searching from the next signature sized block
+                        let position = reader.position().next_multiple_of(0x10);
+                        reader.set_position(position);
+                        continue;
+                    }
+                    // the textures are not always in the same order as the texture formats,
+                    // and the headers can say they are before the texture formats
+                    let end = tfs.0.iter().flat_map(|tf| tf.ranges()).map(|r| r.end).max();
+                    let end = (end.unwrap_or(0) as u64).max(reader.position());
+                    reader.set_position(end);
+                    rejected.append(&mut tfs.1);
+                    tfs.1 = std::mem::take(&mut rejected);
+                    banks.push(tfs);
+                }
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+        }
+        match banks.last_mut() {
+            Some(last) => last.1.append(&mut rejected),
+            // nothing was kept, but what was rejected is still worth knowing
+            None if !rejected.is_empty() => banks.push(Self(Vec::new(), rejected, Vec::new())),
+            None => (),
+        }
+        Ok(banks)
+    }
+
+    /// bytes, where the texture formats end, including the end marker
+    pub fn end(&self) -> u32 {
+        self.0.last().map_or(0, |tf| tf.header + 0x110)
+    }
+
+    /// place each texture right after the previous one, starting at tex_location,
+    /// with its palette right after it
+    pub fn calculate_locations(texture_formats: &mut [TextureFormat], mut tex_location: u32) {
+        for tf in texture_formats.iter_mut() {
+            tf.location = tex_location;
+            tf.palette_location = tex_location.saturating_add(tf.texture_len());
+            // rejected texture formats can be big enough to overflow
+            tex_location = tex_location.saturating_add(tf.len());
+        }
     }
 }
 
+/// size, encoding and location of a texture
 #[derive(Clone, Copy)]
 pub struct TextureFormat {
-    // pixels?
+    /// pixels, the size of the decoded texture
     pub size: Vec2<u32>,
-    // bits
+    /// bits
     pub color_depth: u8,
-    // bits
+    /// bits
     pub pixel_encoding: u8,
-    // bytes
+    /// bytes
     pub location: u32,
+    /// bytes
+    pub palette_location: u32,
+    /// the header says where the texture and palette are, instead of them being estimated
+    pub addressed: bool,
+    /// bytes, where the signature of this texture format is
+    pub header: u32,
+    /// the texture is uploaded as 32-bit pixels, so is stored at half size
+    pub double_size: bool,
+    /// the header as it was read
+    pub gs: TextureHeader,
+}
+
+impl TextureFormat {
+    /// decode the header at header, and check that it describes a texture this can read
+    pub fn new(header: u32, gs: TextureHeader) -> Result<Self> {
+        let (Some(size), Some(color_depth), Some(pixel_encoding)) =
+            (gs.size(), gs.color_depth(), gs.pixel_encoding())
+        else {
+            return Err(gs.unsupported(header));
+        };
+        let tf = Self {
+            size,
+            color_depth,
+            pixel_encoding,
+            location: 0, // not set yet
+            palette_location: 0,
+            addressed: false,
+            header,
+            double_size: gs.converted(),
+            gs,
+        };
+        tf.validate_size()?;
+        tf.validate_encoding()?;
+        Ok(tf)
+    }
+
+    /// bytes used by the palette texture and palette
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u32 {
+        self.texture_len() + self.palette_len()
+    }
+
+    /// bytes used by the palette texture
+    pub fn texture_len(&self) -> u32 {
+        self.size.x * self.size.y * self.pixel_encoding as u32 / 8
+    }
+
+    /// bytes used by the palette
+    pub fn palette_len(&self) -> u32 {
+        (1 << self.pixel_encoding) * self.color_depth as u32 / 8
+    }
+
+    /// bytes, where the palette texture and the palette are
+    pub fn ranges(&self) -> [Range<u32>; 2] {
+        [
+            self.location..self.location.saturating_add(self.texture_len()),
+            self.palette_location..self.palette_location.saturating_add(self.palette_len()),
+        ]
+    }
+
+    /// PSM, the pixel storage mode of the texture
+    pub(crate) fn psm(&self) -> u8 {
+        if self.pixel_encoding == 4 {
+            PSMT4
+        } else {
+            PSMT8
+        }
+    }
+
+    /// the size as it is stored in the header
+    fn stored_size(&self) -> Vec2<u32> {
+        let conversion = conversion(self.psm(), self.double_size);
+        Vec2 {
+            x: self.size.x / conversion.x,
+            y: self.size.y / conversion.y,
+        }
+    }
+
+    /// error for a color depth or pixel encoding that can not be read or written
+    pub fn unsupported_encoding(&self) -> Error {
+        Error::UnsupportedEncoding {
+            offset: self.header as _,
+            index: None,
+            encoding: format!(
+                "{}-bit with {}-bit colors",
+                self.pixel_encoding, self.color_depth
+            ),
+        }
+    }
+
+    /// check that the texture can be stored with this size.
+    /// the GS can not draw textures bigger than 1024x1024, and the GIF tag that uploads
+    /// the texture counts at most 0x7FFF quadwords. only textures uploaded as 32-bit pixels
+    /// are scrambled, so only they need a size the 8-bit scramble can handle
+    pub fn validate_size(&self) -> Result<()> {
+        let (x, y) = (self.size.x, self.size.y);
+        let conversion = conversion(self.psm(), self.double_size);
+        let bytes = x as u64 * y as u64 * self.pixel_encoding as u64 / 8;
+        if !x.is_power_of_two()
+            || !y.is_power_of_two()
+            || x > 1024
+            || y > 1024
+            || !x.is_multiple_of(conversion.x)
+            || !y.is_multiple_of(conversion.y)
+            || !bytes.is_multiple_of(0x10)
+            || bytes / 0x10 > 0x7FFF
+            || (self.double_size
+                && self.pixel_encoding == 8
+                && !convert_8bit::supports(x as _, y as _))
+        {
+            return Err(Error::UnsupportedSize {
+                offset: self.header as _,
+                index: None,
+                size: self.size,
+                pixel_encoding: self.pixel_encoding,
+            });
+        }
+        Ok(())
+    }
+
+    /// check that the texture and palette are uploaded the way they say.
+    /// every color depth goes with every pixel encoding, 16 colors of 32 bits are 4 quadwords
+    fn validate_encoding(&self) -> Result<()> {
+        let pixel_bytes = self.size.x as u64 * self.size.y as u64 * self.pixel_encoding as u64 / 8;
+        if self.gs.palette.qwc as u32 * 0x10 != self.palette_len()
+            || self.gs.texture.qwc as u64 * 0x10 != pixel_bytes
+        {
+            return Err(self.gs.unsupported(self.header));
+        }
+        Ok(())
+    }
+
+    /// the upload of the texture with the size of the texture.
+    /// the buffer width only changes with the width, so it is kept as it was otherwise
+    pub fn texture_upload(&self) -> Upload {
+        let stored = self.stored_size();
+        let mut upload = self.gs.texture;
+
+        // buffer width, in 64 pixels. 8-bit and 4-bit buffers are whole pages of 128 pixels
+        if stored.x != upload.rrw {
+            let dbw = stored.x.div_ceil(64);
+            upload.dbw = if self.double_size {
+                dbw
+            } else {
+                dbw.next_multiple_of(2)
+            } as _;
+        }
+
+        // size
+        upload.rrw = stored.x;
+        upload.rrh = stored.y;
+
+        // quadwords uploaded, keeping the end of packet bit
+        let qwc = self.texture_len() / 0x10;
+        upload.nloop = qwc as _;
+        upload.qwc = qwc as _;
+        upload
+    }
+
+    /// write the header with the size of the texture,
+    /// and with the texture and palette locations if the header says where they are
+    pub fn write<T: Write + Seek>(&self, writer: &mut T) -> io::Result<()> {
+        let mut gs = self.gs;
+        gs.texture = self.texture_upload();
+
+        if self.addressed {
+            gs.texture.addr = self.location;
+            gs.palette.addr = self.palette_location;
+        }
+
+        writer.seek(SeekFrom::Start(self.header as _))?;
+        writer.write_all(&gs.to_bytes())
+    }
+
+    /// bytes, the header fields that write changes
+    pub fn written_ranges(&self) -> Vec<Range<u32>> {
+        let field = |offset: usize, len: usize| {
+            self.header + offset as u32..self.header + (offset + len) as u32
+        };
+        let mut ranges = vec![field(TRXREG, 8), field(IMAGE, 2), field(REF, 2)];
+        // ADDR is in the second word of the DMA tag
+        if self.addressed {
+            ranges.push(field(REF + 4, 4));
+            ranges.push(field(PALETTE + REF + 4, 4));
+        }
+        // DBW is in the seventh byte of BITBLTBUF
+        if self.texture_upload().dbw != self.gs.texture.dbw {
+            ranges.push(field(BITBLTBUF + 6, 1));
+        }
+        ranges
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::{
+        palette::Palette,
+        texture_header::{PSMCT16, PSMCT32},
+        Color,
+    };
+
+    // a GS upload like the game stores them, with the fields read by TextureHeader
+    fn upload(dpsm: u8, rrw: u32, rrh: u32, qwc: u16, addr: u32) -> Vec<u8> {
+        let mut upload = vec![0; 0x80];
+        upload[..4].copy_from_slice(&[0x06, 0x00, 0x00, 0x10]);
+        upload[BITBLTBUF + 6] = 1;
+        upload[BITBLTBUF + 7] = dpsm;
+        upload[TRXREG..TRXREG + 4].copy_from_slice(&rrw.to_le_bytes());
+        upload[TRXREG + 4..TRXREG + 8].copy_from_slice(&rrh.to_le_bytes());
+        upload[IMAGE..IMAGE + 2].copy_from_slice(&qwc.to_le_bytes());
+        upload[REF..REF + 2].copy_from_slice(&qwc.to_le_bytes());
+        upload[REF + 3] = 0x30;
+        upload[REF + 4..REF + 8].copy_from_slice(&addr.to_le_bytes());
+        upload
+    }
+
+    // a file with one 16x16 4-bit texture with a 16-bit palette, whose header is at header
+    fn file(len: usize, header: usize, location: u32, palette_location: u32) -> Vec<u8> {
+        file_with_palette(len, header, location, palette_location, PSMCT16)
+    }
+
+    fn file_with_palette(
+        len: usize,
+        header: usize,
+        location: u32,
+        palette_location: u32,
+        palette_psm: u8,
+    ) -> Vec<u8> {
+        let mut data = vec![0; len];
+        let mut headers = upload(PSMT4, 16, 16, 8, location);
+        let palette_qwc = if palette_psm == PSMCT32 { 4 } else { 2 };
+        headers.extend(upload(palette_psm, 8, 2, palette_qwc, palette_location));
+        headers.extend([0x00, 0x00, 0x00, 0x60]);
+        data[header..header + headers.len()].copy_from_slice(&headers);
+        data
+    }
+
+    #[test]
+    fn reads_textures_before_the_texture_formats() {
+        let data = file(0x800, 0x600, 0x10, 0x110);
+        let banks = TextureFormats::read_banks(&data).unwrap();
+        assert_eq!(banks.len(), 1);
+        let tf = banks[0].0[0];
+        assert!(tf.addressed);
+        assert_eq!((tf.location, tf.palette_location), (0x10, 0x110));
+        assert_eq!(tf.size, Vec2 { x: 16, y: 16 });
+    }
+
+    #[test]
+    fn reads_textures_after_the_texture_formats() {
+        let data = file(0x800, 0x100, 0x300, 0x400);
+        let banks = TextureFormats::read_banks(&data).unwrap();
+        assert_eq!(banks.len(), 1);
+        assert_eq!(banks[0].0[0].location, 0x300);
+    }
+
+    #[test]
+    fn reads_4bit_textures_with_32bit_palettes() {
+        let data = file_with_palette(0x800, 0x100, 0x300, 0x400, PSMCT32);
+        let banks = TextureFormats::read_banks(&data).unwrap();
+        assert!(banks[0].1.is_empty());
+        let tf = banks[0].0[0];
+        assert_eq!((tf.color_depth, tf.pixel_encoding), (32, 4));
+        assert_eq!(tf.palette_len(), 0x40);
+
+        // every color of the palette reads back as it was written
+        let palette = Palette(
+            (0..16)
+                .map(|i| Color {
+                    r: i,
+                    g: 2 * i,
+                    b: 3 * i,
+                    a: 0xFF,
+                })
+                .collect(),
+        );
+        let mut cursor = Cursor::new(data);
+        palette.write_to_bin(&mut cursor, tf).unwrap();
+        let read = Palette::read_from_bin(&mut cursor, tf).unwrap();
+        assert_eq!(read.0, palette.0);
+    }
+
+    #[test]
+    fn resizing_changes_the_buffer_width_with_the_width() {
+        let data = file(0x8000, 0x100, 0x300, 0x400);
+        let tf = TextureFormats::read_banks(&data).unwrap()[0].0[0];
+        assert_eq!(tf.gs.texture.dbw, 1);
+
+        // 4-bit buffers are whole pages of 128 pixels wide
+        let wider = TextureFormat {
+            size: Vec2 { x: 256, y: 16 },
+            ..tf
+        };
+        let mut cursor = Cursor::new(data.clone());
+        wider.write(&mut cursor).unwrap();
+        let header = TextureHeader::read(&mut &cursor.get_ref()[0x100..]).unwrap();
+        assert_eq!((header.texture.dbw, header.texture.rrw), (4, 256));
+        assert!(wider.written_ranges().contains(&(0x126..0x127)));
+
+        let higher = TextureFormat {
+            size: Vec2 { x: 16, y: 64 },
+            ..tf
+        };
+        assert_eq!(higher.texture_upload().dbw, 1);
+        assert!(!higher.written_ranges().contains(&(0x126..0x127)));
+    }
+
+    #[test]
+    fn uploads_write_the_blocks_of_their_page_layout() {
+        let blocks = |dbp, dpsm, rrw, rrh| {
+            let upload = Upload {
+                dbp,
+                dbw: 1,
+                dpsm,
+                rrw,
+                rrh,
+                ..Default::default()
+            };
+            upload.blocks().unwrap().into_iter().collect::<Vec<_>>()
+        };
+        assert_eq!(blocks(0, PSMCT32, 32, 32), (0..16).collect::<Vec<_>>());
+        assert_eq!(blocks(0, PSMCT32, 64, 8), [0, 1, 4, 5, 16, 17, 20, 21]);
+        // the second page of a buffer 1 page wide
+        assert_eq!(blocks(0, PSMCT32, 8, 40), [0, 2, 8, 10, 32]);
+        assert_eq!(blocks(0x10, PSMCT16, 16, 16), [0x10, 0x11]);
+        assert_eq!(blocks(0x12, PSMT4, 32, 32), [0x12, 0x13]);
+    }
 }
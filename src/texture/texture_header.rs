@@ -0,0 +1,300 @@
+use crate::{error::Error, vec::Vec2};
+use std::{
+    collections::BTreeSet,
+    io::{self, Read},
+};
+
+/// pixel storage mode of 32-bit colors
+pub const PSMCT32: u8 = 0x00;
+/// pixel storage mode of 16-bit colors
+pub const PSMCT16: u8 = 0x02;
+/// pixel storage mode of 16-bit colors, laid out differently in GS memory than PSMCT16
+pub const PSMCT16S: u8 = 0x0A;
+/// pixel storage mode of 8-bit palette indexes
+pub const PSMT8: u8 = 0x13;
+/// pixel storage mode of 4-bit palette indexes
+pub const PSMT4: u8 = 0x14;
+
+// where each field is in an upload, in bytes
+// 0x00 DMA tag (CNT), 0x10 GIF tag (A+D), 0x20 BITBLTBUF, 0x30 TRXPOS,
+// 0x40 TRXREG, 0x50 TRXDIR, 0x60 GIF tag (IMAGE), 0x70 DMA tag (REF)
+pub(crate) const BITBLTBUF: usize = 0x20;
+pub(crate) const TRXPOS: usize = 0x30;
+pub(crate) const TRXREG: usize = 0x40;
+pub(crate) const IMAGE: usize = 0x60;
+pub(crate) const REF: usize = 0x70;
+// the palette upload is right after the texture upload
+pub(crate) const PALETTE: usize = 0x80;
+
+/// bytes of a texture header
+pub const LEN: usize = 0x100;
+
+/// blocks of GS memory, 4 MB in blocks of 64 words
+pub const GS_BLOCKS: u32 = 0x4000;
+
+/// one GS image upload, a DMA chain that sets up a transfer to GS memory,
+/// and points at the data that is transferred
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Upload {
+    /// BITBLTBUF DBP, where the data goes in GS memory, in 64 words
+    pub dbp: u16,
+    /// BITBLTBUF DBW, width of the buffer, in 64 pixels
+    pub dbw: u8,
+    /// BITBLTBUF DPSM, pixel storage mode of the data
+    pub dpsm: u8,
+    /// TRXPOS DSAX, pixels from the left of the buffer
+    pub dsax: u16,
+    /// TRXPOS DSAY, pixels from the top of the buffer
+    pub dsay: u16,
+    /// TRXREG RRW, width in pixels of dpsm
+    pub rrw: u32,
+    /// TRXREG RRH, height in pixels of dpsm
+    pub rrh: u32,
+    /// IMAGE GIF tag NLOOP, quadwords of image data
+    pub nloop: u16,
+    /// IMAGE GIF tag EOP, whether it ends the GIF packet
+    pub eop: bool,
+    /// REF DMA tag QWC, quadwords of data
+    pub qwc: u16,
+    /// REF DMA tag ADDR, bytes, where the data is
+    pub addr: u32,
+}
+
+impl Upload {
+    fn read(raw: &[u8]) -> Self {
+        Self {
+            dbp: bits(raw, BITBLTBUF, 32, 14) as _,
+            dbw: bits(raw, BITBLTBUF, 48, 6) as _,
+            dpsm: bits(raw, BITBLTBUF, 56, 6) as _,
+            dsax: bits(raw, TRXPOS, 32, 11) as _,
+            dsay: bits(raw, TRXPOS, 48, 11) as _,
+            // the whole words, so nothing is lost when they are written back
+            rrw: bits(raw, TRXREG, 0, 32) as _,
+            rrh: bits(raw, TRXREG, 32, 32) as _,
+            nloop: bits(raw, IMAGE, 0, 15) as _,
+            eop: bits(raw, IMAGE, 15, 1) == 1,
+            qwc: bits(raw, REF, 0, 16) as _,
+            addr: bits(raw, REF, 32, 31) as _,
+        }
+    }
+
+    fn write(&self, raw: &mut [u8]) {
+        set_bits(raw, BITBLTBUF, 32, 14, self.dbp as _);
+        set_bits(raw, BITBLTBUF, 48, 6, self.dbw as _);
+        set_bits(raw, BITBLTBUF, 56, 6, self.dpsm as _);
+        set_bits(raw, TRXPOS, 32, 11, self.dsax as _);
+        set_bits(raw, TRXPOS, 48, 11, self.dsay as _);
+        set_bits(raw, TRXREG, 0, 32, self.rrw as _);
+        set_bits(raw, TRXREG, 32, 32, self.rrh as _);
+        set_bits(raw, IMAGE, 0, 15, self.nloop as _);
+        set_bits(raw, IMAGE, 15, 1, self.eop as _);
+        set_bits(raw, REF, 0, 16, self.qwc as _);
+        set_bits(raw, REF, 32, 31, self.addr as _);
+    }
+
+    /// the blocks of GS memory the upload writes to, counted like DBP,
+    /// none if the layout of its pixel storage mode is not known
+    pub fn blocks(&self) -> Option<BTreeSet<u32>> {
+        let (block, order) = block_layout(self.dpsm)?;
+        let page = page(self.dpsm);
+        let columns = page.x / block.x;
+        let rows = page.y / block.y;
+        // pages in each row of the buffer
+        let width = (self.dbw as u32 * 64 / page.x).max(1);
+
+        let mut blocks = BTreeSet::new();
+        if self.rrw == 0 || self.rrh == 0 {
+            return Some(blocks);
+        }
+        let (x, y) = (self.dsax as u32, self.dsay as u32);
+        for block_y in y / block.y..=(y + self.rrh - 1) / block.y {
+            for block_x in x / block.x..=(x + self.rrw - 1) / block.x {
+                let page = block_y / rows * width + block_x / columns;
+                let block = order[(block_y % rows * columns + block_x % columns) as usize];
+                blocks.insert(self.dbp as u32 + page * 32 + block);
+            }
+        }
+        Some(blocks)
+    }
+}
+
+// len bits of the little endian doubleword at offset, from shift
+fn bits(raw: &[u8], offset: usize, shift: u32, len: u32) -> u64 {
+    let word = u64::from_le_bytes(raw[offset..offset + 8].try_into().unwrap());
+    (word >> shift) & ((1 << len) - 1)
+}
+
+fn set_bits(raw: &mut [u8], offset: usize, shift: u32, len: u32, value: u64) {
+    let mask = ((1 << len) - 1) << shift;
+    let word = u64::from_le_bytes(raw[offset..offset + 8].try_into().unwrap());
+    let word = (word & !mask) | ((value << shift) & mask);
+    raw[offset..offset + 8].copy_from_slice(&word.to_le_bytes());
+}
+
+/// pixels of a GS page, width and height
+fn page(psm: u8) -> Vec2<u32> {
+    match psm {
+        PSMT8 => Vec2 { x: 128, y: 64 },
+        PSMT4 => Vec2 { x: 128, y: 128 },
+        PSMCT16 | PSMCT16S => Vec2 { x: 64, y: 64 },
+        _ => Vec2 { x: 64, y: 32 },
+    }
+}
+
+// which block of a page each block is, row by row. 32-bit and 8-bit pages are 8 blocks wide,
+// 16-bit and 4-bit pages are 4 blocks wide
+const BLOCKS_32: [u32; 32] = [
+    0, 1, 4, 5, 16, 17, 20, 21, 2, 3, 6, 7, 18, 19, 22, 23, 8, 9, 12, 13, 24, 25, 28, 29, 10, 11,
+    14, 15, 26, 27, 30, 31,
+];
+const BLOCKS_16: [u32; 32] = [
+    0, 2, 8, 10, 1, 3, 9, 11, 4, 6, 12, 14, 5, 7, 13, 15, 16, 18, 24, 26, 17, 19, 25, 27, 20, 22,
+    28, 30, 21, 23, 29, 31,
+];
+const BLOCKS_16S: [u32; 32] = [
+    0, 2, 16, 18, 1, 3, 17, 19, 8, 10, 24, 26, 9, 11, 25, 27, 4, 6, 20, 22, 5, 7, 21, 23, 12, 14,
+    28, 30, 13, 15, 29, 31,
+];
+
+/// pixels of a GS block, width and height, and the order of the blocks of a page
+fn block_layout(psm: u8) -> Option<(Vec2<u32>, &'static [u32; 32])> {
+    match psm {
+        PSMCT32 => Some((Vec2 { x: 8, y: 8 }, &BLOCKS_32)),
+        PSMCT16 => Some((Vec2 { x: 16, y: 8 }, &BLOCKS_16)),
+        PSMCT16S => Some((Vec2 { x: 16, y: 8 }, &BLOCKS_16S)),
+        PSMT8 => Some((Vec2 { x: 16, y: 16 }, &BLOCKS_32)),
+        PSMT4 => Some((Vec2 { x: 32, y: 16 }, &BLOCKS_16)),
+        _ => None,
+    }
+}
+
+/// how many pixels of psm each uploaded pixel is, width and height.
+/// a page of 32-bit pixels is the same memory as a page of indexed pixels,
+/// which is twice as wide, and twice as high for 8-bit or four times as high for 4-bit
+pub fn conversion(psm: u8, converted: bool) -> Vec2<u32> {
+    if !converted {
+        return Vec2 { x: 1, y: 1 };
+    }
+    let (upload, drawn) = (page(PSMCT32), page(psm));
+    Vec2 {
+        x: drawn.x / upload.x,
+        y: drawn.y / upload.y,
+    }
+}
+
+/// the upload of a texture, and of its palette, as they are stored before the textures.
+/// raw is kept, so the bytes that are not decoded are written back as they were
+#[derive(Clone, Copy)]
+pub struct TextureHeader {
+    /// the header as it was read
+    pub raw: [u8; LEN],
+    /// the upload of the palette texture
+    pub texture: Upload,
+    /// the upload of the palette
+    pub palette: Upload,
+}
+
+impl TextureHeader {
+    /// read a header, and decode both uploads
+    pub fn read<T: Read>(reader: &mut T) -> io::Result<Self> {
+        let mut raw = [0; LEN];
+        reader.read_exact(&mut raw)?;
+        Ok(Self {
+            raw,
+            texture: Upload::read(&raw),
+            palette: Upload::read(&raw[PALETTE..]),
+        })
+    }
+
+    /// raw, with the decoded fields written over it
+    pub fn to_bytes(&self) -> [u8; LEN] {
+        let mut raw = self.raw;
+        self.texture.write(&mut raw);
+        self.palette.write(&mut raw[PALETTE..]);
+        raw
+    }
+
+    /// bits, 16 or 32, from the pixel storage mode of the palette
+    pub fn color_depth(&self) -> Option<u8> {
+        match self.palette.dpsm {
+            PSMCT32 => Some(32),
+            PSMCT16 | PSMCT16S => Some(16),
+            _ => None,
+        }
+    }
+
+    /// bits, 4 or 8, from the colors in the palette
+    pub fn pixel_encoding(&self) -> Option<u8> {
+        match self.palette.rrw as u64 * self.palette.rrh as u64 {
+            16 => Some(4),
+            256 => Some(8),
+            _ => None,
+        }
+    }
+
+    /// PSM, the pixel storage mode the texture is drawn with. indexed textures are often
+    /// uploaded as 32-bit pixels, which the GS stores the same way as the indexed pixels
+    pub fn psm(&self) -> Option<u8> {
+        match (self.texture.dpsm, self.pixel_encoding()?) {
+            (PSMT4, 4) | (PSMCT32, 4) => Some(PSMT4),
+            (PSMT8, 8) | (PSMCT32, 8) => Some(PSMT8),
+            _ => None,
+        }
+    }
+
+    /// whether the texture is uploaded as 32-bit pixels
+    pub fn converted(&self) -> bool {
+        self.texture.dpsm == PSMCT32
+    }
+
+    /// pixels, the uploaded size in pixels of psm
+    pub fn size(&self) -> Option<Vec2<u32>> {
+        let conversion = conversion(self.psm()?, self.converted());
+        Some(Vec2 {
+            x: self.texture.rrw.checked_mul(conversion.x)?,
+            y: self.texture.rrh.checked_mul(conversion.y)?,
+        })
+    }
+
+    /// bytes, where the DMA tags say the palette texture and the palette are,
+    /// if they both say somewhere in the file, aligned to quadwords like DMA needs
+    pub fn locations(
+        &self,
+        texture_len: u32,
+        palette_len: u32,
+        file_len: u64,
+    ) -> Option<(u32, u32)> {
+        let valid = |addr: u32, len: u32| {
+            addr != 0 && addr.is_multiple_of(0x10) && addr as u64 + len as u64 <= file_len
+        };
+        (valid(self.texture.addr, texture_len) && valid(self.palette.addr, palette_len))
+            .then_some((self.texture.addr, self.palette.addr))
+    }
+
+    /// TW and TH, log2 of the size
+    pub fn tw_th(&self) -> Option<Vec2<u32>> {
+        let size = self.size()?;
+        Some(Vec2 {
+            x: size.x.trailing_zeros(),
+            y: size.y.trailing_zeros(),
+        })
+    }
+
+    /// error for a header that does not decode into a texture this can read and write
+    pub fn unsupported(&self, offset: u32) -> Error {
+        Error::UnsupportedEncoding {
+            offset: offset as _,
+            index: None,
+            encoding: format!(
+                "texture storage mode {:#04x} with {} quadwords, palette storage mode {:#04x} \
+                with {}x{} colors and {} quadwords",
+                self.texture.dpsm,
+                self.texture.qwc,
+                self.palette.dpsm,
+                self.palette.rrw,
+                self.palette.rrh,
+                self.palette.qwc
+            ),
+        }
+    }
+}
@@ -1,11 +1,20 @@
 use num_traits::Num;
 
-#[derive(Clone, Copy)]
+/// 2D vector
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Vec2<Type> {
+    /// width, or horizontal
     pub x: Type,
+    /// height, or vertical
     pub y: Type,
 }
 
+impl<T: std::fmt::Display> std::fmt::Display for Vec2<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "[{}, {}]", self.x, self.y)
+    }
+}
+
 impl<T> std::ops::Mul<T> for Vec2<T>
 where
     T: Num + Copy,
@@ -20,9 +29,14 @@ where
     }
 }
 
+/// 3D vector
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Vec3<Type> {
+    /// x axis
     pub x: Type,
+    /// y axis
     pub y: Type,
+    /// z axis
     pub z: Type,
 }
 
@@ -30,6 +44,7 @@ impl<T> Vec3<T>
 where
     T: Num + Copy,
 {
+    /// cross product, perpendicular to both
     pub fn cross(&self, other: &Vec3<T>) -> Vec3<T> {
         Vec3 {
             x: self.y * other.z - self.z * other.y,
@@ -38,10 +53,12 @@ where
         }
     }
 
+    /// dot product
     pub fn dot(&self, other: &Vec3<T>) -> T {
         self.x * other.x + self.y * other.y + self.z * other.z
     }
 
+    /// convert each component
     pub fn to<U: From<T>>(&self) -> Vec3<U> {
         Vec3 {
             x: U::from(self.x),
@@ -51,6 +68,26 @@ where
     }
 }
 
+impl Vec3<f32> {
+    /// euclidean length
+    pub fn length(&self) -> f32 {
+        self.dot(self).sqrt()
+    }
+
+    /// unit length, none if it has no length
+    pub fn normalize(&self) -> Option<Vec3<f32>> {
+        let len = self.length();
+        if len == 0. || !len.is_finite() {
+            return None;
+        }
+        Some(Vec3 {
+            x: self.x / len,
+            y: self.y / len,
+            z: self.z / len,
+        })
+    }
+}
+
 impl<T> std::ops::Add<&Vec3<T>> for &Vec3<T>
 where
     T: Num + Copy,